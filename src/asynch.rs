@@ -0,0 +1,1192 @@
+//! Async driver for the Notecard on top of
+//! [embedded-hal-async](https://docs.rs/embedded-hal-async/1.0.0/embedded_hal_async/), enabled by
+//! the `async` feature.
+//!
+//! The [`AsyncNotecard`] mirrors the blocking [`Notecard`](crate::Notecard), but yields to the
+//! executor while waiting between chunks and for responses. The request and response types from
+//! [`card`](crate::card), [`note`](crate::note), [`hub`](crate::hub), [`web`](crate::web),
+//! [`dfu`](crate::dfu) and [`ntn`](crate::ntn) are shared.
+//!
+//! ```ignore
+//! use blues_notecard::asynch::{AsyncNotecard, I2c};
+//!
+//! let mut note: AsyncNotecard<_> = AsyncNotecard::new(I2c::new(i2c, 0x17), Default::default());
+//! note.initialize(&mut delay).await?;
+//!
+//! let status = note.card().status(&mut delay).await?.wait(&mut delay).await?;
+//! ```
+
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::pin::pin;
+use core::task::Poll;
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use heapless::Vec;
+use serde::de::{DeserializeOwned, IgnoredAny};
+
+use crate::error::{is_error_response, new_request_name, RequestName};
+use crate::protocol::{Protocol, Step};
+use crate::stream::{Base64Field, Sink};
+use crate::writer::{Part, RequestWriter};
+use crate::{
+    check_response, crc, deserialize_response, notecard_error, raw_idempotent, raw_request,
+    request_name, Clock, FirmwareVersion, NoClock, NoteError, NoteState, NotecardConfig,
+    NotecardRequest, Resend, Timer, DEFAULT_BUF_SIZE, READ_LENGTH_MAX, RESPONSE_DELAY,
+};
+
+pub mod card;
+pub mod dfu;
+pub mod hub;
+pub mod note;
+pub mod ntn;
+pub mod web;
+
+mod i2c;
+
+pub use i2c::I2c;
+
+/// An async bus the Notecard is connected to, see [`Transport`](crate::transport::Transport).
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    /// Check if the Notecard is connected and responding.
+    async fn ping(&mut self) -> bool;
+
+    /// Query the Notecard for the number of bytes that are ready to be read.
+    async fn data_query(&mut self) -> Result<usize, NoteError>;
+
+    /// Read the next part of a response into the start of `buf`. Returns the number of bytes read
+    /// and the number of bytes still remaining, see
+    /// [`Transport::read`](crate::transport::Transport::read).
+    async fn read(&mut self, avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError>;
+
+    /// Write a chunk of a request.
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError>;
+}
+
+/// The ATTN pin of the Notecard, see [`Attn`](crate::Attn).
+#[allow(async_fn_in_trait)]
+pub trait AsyncAttn {
+    /// Wait for at most `timeout` ms for ATTN to be asserted. Returns the time waited (ms), see
+    /// [`Attn::wait`](crate::Attn::wait).
+    async fn wait(&mut self, delay: &mut impl DelayNs, timeout: u32) -> u32;
+}
+
+/// An input pin connected to ATTN, waiting for it to go high.
+pub struct AttnPin<P: Wait>(pub P);
+
+impl<P: Wait> AsyncAttn for AttnPin<P> {
+    async fn wait(&mut self, delay: &mut impl DelayNs, timeout: u32) -> u32 {
+        let mut waited = 0;
+
+        // The pin is waited for in steps, so that the time waited can be counted without a clock.
+        while waited < timeout {
+            let step = (timeout - waited).min(RESPONSE_DELAY.into());
+            let mut high = pin!(self.0.wait_for_high());
+            let mut step_delay = pin!(delay.delay_ms(step));
+
+            // A pin that can not be waited for is taken as asserted, so that the Notecard is
+            // polled.
+            let asserted = poll_fn(|cx| match high.as_mut().poll(cx) {
+                Poll::Ready(_) => Poll::Ready(true),
+                Poll::Pending => step_delay.as_mut().poll(cx).map(|_| false),
+            })
+            .await;
+
+            if asserted {
+                break;
+            }
+            waited += step;
+        }
+
+        waited
+    }
+}
+
+/// Without ATTN the Notecard is polled for the response. The default of the driver.
+pub struct NoAttn;
+
+impl AsyncAttn for NoAttn {
+    async fn wait(&mut self, _delay: &mut impl DelayNs, _timeout: u32) -> u32 {
+        0
+    }
+}
+
+/// The async driver for the Notecard. Must be intialized before making any requests.
+pub struct AsyncNotecard<
+    IOM: AsyncTransport,
+    const BUF_SIZE: usize = DEFAULT_BUF_SIZE,
+    C: Clock = NoClock,
+    A: AsyncAttn = NoAttn,
+> {
+    iom: IOM,
+    proto: Protocol<BUF_SIZE>,
+
+    clock: C,
+    attn: A,
+}
+
+impl<IOM: AsyncTransport, const BUF_SIZE: usize> AsyncNotecard<IOM, BUF_SIZE> {
+    /// Create a driver for a Notecard connected through `iom`. The `i2c_addr` of the config is not
+    /// used, it is given to [`I2c`] directly.
+    pub fn new(iom: IOM, c: NotecardConfig) -> AsyncNotecard<IOM, BUF_SIZE> {
+        AsyncNotecard {
+            iom,
+            proto: Protocol::new(&c),
+            clock: NoClock,
+            attn: NoAttn,
+        }
+    }
+}
+
+impl<IOM: AsyncTransport, const BUF_SIZE: usize, C: Clock, A: AsyncAttn>
+    AsyncNotecard<IOM, BUF_SIZE, C, A>
+{
+    /// Measure timeouts with a monotonic `clock`, see
+    /// [`Notecard::with_clock`](crate::Notecard::with_clock).
+    pub fn with_clock<C2: Clock>(self, clock: C2) -> AsyncNotecard<IOM, BUF_SIZE, C2, A> {
+        AsyncNotecard { clock, ..self }
+    }
+
+    /// Wait for responses on the ATTN pin, see
+    /// [`Notecard::with_attn`](crate::Notecard::with_attn).
+    pub fn with_attn<A2: AsyncAttn>(self, attn: A2) -> AsyncNotecard<IOM, BUF_SIZE, C, A2> {
+        AsyncNotecard { attn, ..self }
+    }
+
+    /// Free the transport.
+    pub fn free(self) -> IOM {
+        self.iom
+    }
+
+    /// Partial data of the last response, deserialized as `D`, if it was an error response. See
+    /// [`Notecard::error_data`](crate::Notecard::error_data).
+    pub fn error_data<D: DeserializeOwned>(&self) -> Option<D> {
+        self.proto.error_data()
+    }
+
+    /// Initialize the notecard driver by performing handshake with notecard, and [`tune`] it if
+    /// [`NotecardConfig::auto_tune`] is set.
+    ///
+    /// [`tune`]: Self::tune
+    pub async fn initialize(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        info!("note: initializing.");
+        self.reset(delay).await?;
+
+        if self.proto.auto_tune {
+            self.tune(delay).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Request `card.version`, and use larger chunks and shorter delays when transmitting if the
+    /// Notecard firmware is version 3.4 or above, see [`Notecard::tune`](crate::Notecard::tune).
+    pub async fn tune(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        let request = self
+            .request_raw(delay, b"{\"req\":\"card.version\"}\n")
+            .await?;
+        let version: FirmwareVersion = AsyncFutureResponse::from(&mut *self)
+            .keep(request)
+            .wait(delay)
+            .await?;
+        self.proto.tune(&version);
+
+        Ok(())
+    }
+
+    /// Check if notecarrier is connected and responding.
+    ///
+    /// > This is allowed no matter the state.
+    pub async fn ping(&mut self) -> bool {
+        self.iom.ping().await
+    }
+
+    /// Query the notecard for available bytes.
+    pub async fn data_query(&mut self) -> Result<usize, NoteError> {
+        self.proto.query()?;
+        let available = self.iom.data_query().await?;
+        Ok(self.proto.queried(available))
+    }
+
+    /// Poll for data.
+    async fn poll(&mut self) -> Result<Option<&[u8]>, NoteError> {
+        loop {
+            match self.proto.step()? {
+                Step::Query => {
+                    // 1. Check for available data
+                    let sz = self.data_query().await?;
+                    if sz > 0 {
+                        debug!("response ready: {} bytes..", sz);
+                    } else {
+                        // sleep and wait for ready.
+                        return Ok(None);
+                    }
+                }
+                Step::Read(avail) => {
+                    let (len, buf) = self.proto.read_buf()?;
+                    let r = self.iom.read(avail, buf).await;
+                    if self.proto.read(len, r)? > 0 {
+                        // sleep and wait for more data.
+                        return Ok(None);
+                    }
+                }
+                Step::Discard(avail) => {
+                    let r = self.iom.read(avail, self.proto.discard_buf()?).await;
+                    if self.proto.discarded(r)? > 0 {
+                        return Ok(None);
+                    }
+
+                    return Err(NoteError::BufOverflow);
+                }
+                Step::Ready => return Ok(Some(self.proto.take_response()?)),
+            }
+        }
+    }
+
+    /// Poll for data, but read the response into `chunk` instead of the buffer, see
+    /// [`Notecard`](crate::Notecard).
+    async fn poll_chunk<'c>(&mut self, chunk: &'c mut [u8]) -> Result<(&'c [u8], bool), NoteError> {
+        if let Step::Query = self.proto.chunk_step()?
+            && self.data_query().await? == 0
+        {
+            return Ok((&[], false));
+        }
+
+        match self.proto.chunk_step()? {
+            Step::Read(avail) => {
+                let (sz, available) = self.iom.read(avail, chunk).await?;
+                self.proto.chunk_read(available);
+
+                Ok((&chunk[..sz], available == 0))
+            }
+            _ => Err(NoteError::WrongState),
+        }
+    }
+
+    /// Read any remaining data from the Notecarrier. This will cancel any waiting responses, and
+    /// waiting for a response after this call will time-out.
+    async fn consume_response(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        warn!("note: trying to consume any left-over response.");
+        let mut timer = Timer::new(&mut self.clock);
+
+        while timer.elapsed(&mut self.clock) < self.proto.response_timeout {
+            if (self.poll().await?).is_some() {
+                self.proto.buf.clear();
+                return Ok(());
+            }
+
+            delay.delay_ms(RESPONSE_DELAY as u32).await;
+            timer.delayed(RESPONSE_DELAY.into());
+        }
+
+        self.proto.buf.clear();
+
+        error!("response timed out (>= {}).", self.proto.response_timeout);
+        Err(NoteError::TimeOut)
+    }
+
+    /// Reset notecard driver and state. Any waiting responses will be invalidated
+    /// and time-out.
+    pub async fn reset(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        self.proto.reset();
+        self.handshake(delay).await
+    }
+
+    async fn handshake(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        if matches!(self.proto.state, NoteState::Handshake) {
+            debug!("note: handshake");
+            if self.data_query().await? > 0 {
+                error!("note: handshake: remaining data in queue, consuming..");
+                self.consume_response(delay).await?;
+            }
+
+            self.proto.answered();
+        }
+        Ok(())
+    }
+
+    /// Make the driver ready for a new request, draining an abandoned response, see
+    /// [`NoteState`].
+    async fn ready(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        if self.proto.abandoned() {
+            if let Err(e) = self.consume_response(delay).await {
+                warn!("note: failed to drain abandoned response: {:?}", e);
+                return self.reset(delay).await;
+            }
+
+            self.proto.answered();
+        }
+
+        Ok(())
+    }
+
+    /// Reset the driver before a request if it is in the wrong state, or if a stale response is
+    /// waiting after the last request was not answered.
+    async fn prepare(&mut self, delay: &mut impl DelayNs) -> Result<(), NoteError> {
+        if matches!(self.proto.state, NoteState::Request)
+            && self.proto.unanswered
+            && self.iom.data_query().await? > 0
+        {
+            warn!("note: request: stale response waiting, resetting before new request.");
+            self.proto.state = NoteState::Handshake;
+        }
+
+        if !matches!(self.proto.state, NoteState::Request) {
+            warn!("note: request: wrong-state, resetting before new request.");
+            self.reset(delay).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a request, or a command, to the Notecard, see
+    /// [`Notecard::stream`](crate::Notecard). Errors while preparing the driver are returned after
+    /// the request has been started, so that it can be retried.
+    async fn stream(
+        &mut self,
+        delay: &mut impl DelayNs,
+        command: bool,
+        name: RequestName,
+        timeout: Option<u32>,
+        request: &impl Resend,
+    ) -> Result<(), NoteError> {
+        let prepared = self.prepare(delay).await;
+        let seq = self.proto.begin(name, timeout);
+        prepared?;
+
+        self.write_request(delay, command, seq, request).await
+    }
+
+    /// Write a request with the sequence number `seq`. The transport can not be written to while
+    /// the request is serialized, so the request is serialized again for every segment, and only
+    /// the segment is buffered, see [`Part`].
+    async fn write_request(
+        &mut self,
+        delay: &mut impl DelayNs,
+        command: bool,
+        seq: Option<u16>,
+        request: &impl Resend,
+    ) -> Result<(), NoteError> {
+        let mut pacing = self.proto.pacing();
+        let mut written = 0;
+
+        loop {
+            let part = Part::new(written, self.proto.segment_length);
+            let mut writer = RequestWriter::new(part, command, seq);
+            let r = request.resend(&mut writer).and_then(|_| writer.finish());
+            let part = writer.into_inner();
+
+            match r {
+                Err(_) if part.more => {}
+                r => r?,
+            }
+
+            for c in part.bytes.chunks(self.proto.chunk_length) {
+                self.iom.write(c).await?;
+                delay.delay_ms(pacing.chunk(c.len()).into()).await;
+            }
+            written += part.bytes.len();
+
+            if !part.more {
+                break;
+            }
+        }
+        delay.delay_ms(pacing.finish().into()).await;
+
+        Ok(())
+    }
+
+    /// Reset and send the kept `request` again after the transient error `e`, if allowed by the
+    /// retry policy. Otherwise `e` is returned.
+    async fn retry(
+        &mut self,
+        delay: &mut impl DelayNs,
+        mut e: NoteError,
+        request: Option<&impl Resend>,
+    ) -> Result<(), NoteError> {
+        let Some(request) = request else {
+            return Err(e);
+        };
+
+        while self.proto.retrying(&e) {
+            delay.delay_ms(self.proto.retry_policy.delay as u32).await;
+
+            if let Err(err) = self.reset(delay).await {
+                e = err;
+                continue;
+            }
+
+            // The request is sent with the same sequence number.
+            let seq = self.proto.response_seq();
+
+            match self.write_request(delay, false, seq, request).await {
+                Ok(()) => {
+                    self.proto.sent();
+                    return Ok(());
+                }
+                Err(err) => e = err,
+            }
+        }
+
+        Err(e)
+    }
+
+    /// Make a raw request. The byte slice must be a single line ending with `\n`. After making a
+    /// request a [AsyncFutureResponse] must be created and consumed. The request is returned
+    /// without the newline if it is kept for retrying.
+    pub(crate) async fn request_raw<'r>(
+        &mut self,
+        delay: &mut impl DelayNs,
+        cmd: &'r [u8],
+    ) -> Result<Option<&'r [u8]>, NoteError> {
+        let cmd = raw_request(cmd)?;
+        self.ready(delay).await?;
+        let name = new_request_name(request_name(cmd));
+        let kept = self.proto.keeps(raw_idempotent(cmd)).then_some(cmd);
+
+        match self.stream(delay, false, name, None, &cmd).await {
+            Ok(()) => self.proto.sent(),
+            Err(e) => self.retry(delay, e, kept.as_ref()).await?,
+        }
+
+        Ok(kept)
+    }
+
+    /// Make a request. After making a request a [AsyncFutureResponse] must be created and
+    /// consumed before making any new requests. The request does not need to fit in the buffer.
+    /// The request is returned if it is kept for retrying.
+    pub(crate) async fn request<R: NotecardRequest>(
+        &mut self,
+        delay: &mut impl DelayNs,
+        cmd: R,
+    ) -> Result<Option<R>, NoteError> {
+        self.ready(delay).await?;
+        let name = new_request_name(Some(R::NAME));
+
+        let r = self.stream(delay, false, name, cmd.timeout(), &cmd).await;
+        let kept = self.proto.keeps(cmd.idempotent()).then_some(cmd);
+
+        match r {
+            Ok(()) => self.proto.sent(),
+            Err(e) => self.retry(delay, e, kept.as_ref()).await?,
+        }
+
+        Ok(kept)
+    }
+
+    /// Send a request as a command, which the Notecard does not respond to. The driver is ready
+    /// for new requests as soon as the command has been sent.
+    pub(crate) async fn command<R: NotecardRequest>(
+        &mut self,
+        delay: &mut impl DelayNs,
+        cmd: R,
+    ) -> Result<(), NoteError> {
+        self.ready(delay).await?;
+        let name = new_request_name(Some(R::NAME));
+        self.stream(delay, true, name, None, &cmd).await?;
+
+        self.proto.answered();
+
+        Ok(())
+    }
+
+    /// Send a request, see [`Notecard::send`](crate::Notecard::send).
+    pub async fn send<R: NotecardRequest>(
+        &mut self,
+        delay: &mut impl DelayNs,
+        request: R,
+    ) -> Result<AsyncFutureResponse<'_, R::Response, IOM, BUF_SIZE, C, A, R>, NoteError> {
+        let request = self.request(delay, request).await?;
+        Ok(AsyncFutureResponse::from(self).keep(request))
+    }
+
+    /// Send a raw JSON request, see [`Notecard::send_raw`](crate::Notecard::send_raw).
+    pub async fn send_raw<'r>(
+        &mut self,
+        delay: &mut impl DelayNs,
+        request: &'r [u8],
+    ) -> Result<AsyncFutureResponse<'_, IgnoredAny, IOM, BUF_SIZE, C, A, &'r [u8]>, NoteError> {
+        let request = self.request_raw(delay, request).await?;
+        Ok(AsyncFutureResponse::from(self).keep(request))
+    }
+
+    /// [card Requests](https://dev.blues.io/reference/notecard-api/card-requests/)
+    pub fn card(&mut self) -> card::Card<'_, IOM, BUF_SIZE, C, A> {
+        card::Card::from(self)
+    }
+
+    /// [note Requests](https://dev.blues.io/reference/notecard-api/note-requests/)
+    pub fn note(&mut self) -> note::Note<'_, IOM, BUF_SIZE, C, A> {
+        note::Note::from(self)
+    }
+
+    /// [web Requests](https://dev.blues.io/reference/notecard-api/web-requests/)
+    pub fn web(&mut self) -> web::Web<'_, IOM, BUF_SIZE, C, A> {
+        web::Web::from(self)
+    }
+
+    /// [hub Requests](https://dev.blues.io/reference/notecard-api/hub-requests/)
+    pub fn hub(&mut self) -> hub::Hub<'_, IOM, BUF_SIZE, C, A> {
+        hub::Hub::from(self)
+    }
+
+    /// [dfu Requests](https://dev.blues.io/api-reference/notecard-api/dfu-requests/)
+    pub fn dfu(&mut self) -> dfu::DFU<'_, IOM, BUF_SIZE, C, A> {
+        dfu::DFU::from(self)
+    }
+
+    /// [NtN Requests](https://dev.blues.io/reference/notecard-api/ntn-requests/)
+    pub fn ntn(&mut self) -> ntn::NTN<'_, IOM, BUF_SIZE, C, A> {
+        ntn::NTN::from(self)
+    }
+}
+
+/// A future response, see [`FutureResponse`](crate::FutureResponse).
+#[must_use = "The response must be waited for and consumed, otherwise the next request waits for it"]
+pub struct AsyncFutureResponse<
+    'a,
+    T: DeserializeOwned,
+    IOM: AsyncTransport,
+    const BUF_SIZE: usize,
+    C: Clock = NoClock,
+    A: AsyncAttn = NoAttn,
+    R: Resend = &'static [u8],
+> {
+    note: &'a mut AsyncNotecard<IOM, BUF_SIZE, C, A>,
+
+    /// Timeout while waiting for response (ms).
+    timeout: u32,
+
+    /// The request, kept if it is sent again on errors.
+    request: Option<R>,
+
+    _r: PhantomData<T>,
+}
+
+impl<
+        'a,
+        T: DeserializeOwned,
+        IOM: AsyncTransport,
+        const BUF_SIZE: usize,
+        C: Clock,
+        A: AsyncAttn,
+        R: Resend,
+    > AsyncFutureResponse<'a, T, IOM, BUF_SIZE, C, A, R>
+{
+    fn from(
+        note: &'a mut AsyncNotecard<IOM, BUF_SIZE, C, A>,
+    ) -> AsyncFutureResponse<'a, T, IOM, BUF_SIZE, C, A, R> {
+        AsyncFutureResponse {
+            timeout: note.proto.timeout,
+            note,
+            request: None,
+            _r: PhantomData,
+        }
+    }
+
+    /// Keep the request returned by [`AsyncNotecard::request`] or
+    /// [`AsyncNotecard::request_raw`] for sending it again on errors.
+    fn keep(mut self, request: Option<R>) -> Self {
+        self.request = request;
+        self
+    }
+
+    /// Set the timeout while waiting for this response (ms), see
+    /// [`FutureResponse::timeout`](crate::FutureResponse::timeout).
+    pub fn timeout(mut self, timeout: u32) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reads remaining data and returns the deserialized object if it is ready.
+    pub async fn poll(&mut self) -> Result<Option<T>, NoteError> {
+        let crc = self.note.proto.response_seq();
+
+        match self.note.poll().await? {
+            Some(body) => deserialize_response(body, crc)
+                .map(Some)
+                .map_err(|e| e.in_request(&self.note.proto.request)),
+            None => Ok(None),
+        }
+    }
+
+    /// Wait for the response and return it as it was received, see
+    /// [`FutureResponse::wait_raw`](crate::FutureResponse::wait_raw).
+    pub async fn wait_raw(self, delay: &mut impl DelayNs) -> Result<&'a [u8], NoteError> {
+        let timeout = self.timeout;
+        let request = self.request;
+        let note = self.note;
+        let crc = note.proto.response_seq();
+        let mut timer = Timer::new(&mut note.clock);
+
+        loop {
+            let e = match note.poll().await {
+                Ok(Some(body)) => match check_response(body, crc) {
+                    Ok(()) => break,
+                    Err(e) => e.in_request(&note.proto.request),
+                },
+                Ok(None) => {
+                    delay.delay_ms(RESPONSE_DELAY as u32).await;
+                    timer.delayed(RESPONSE_DELAY.into());
+
+                    if timer.elapsed(&mut note.clock) < timeout {
+                        continue;
+                    }
+
+                    error!("response timed out (>= {}).", timeout);
+                    NoteError::TimeOut
+                }
+                Err(e) => e,
+            };
+
+            note.retry(delay, e, request.as_ref()).await?;
+            timer = Timer::new(&mut note.clock);
+        }
+
+        Ok(&note.proto.buf)
+    }
+
+    /// Read the response in chunks until it is complete, passing each chunk to `f` together with
+    /// the buffer of the driver. The sequence number and CRC are checked at the end.
+    async fn stream(
+        &mut self,
+        delay: &mut impl DelayNs,
+        mut f: impl FnMut(&mut Vec<u8, BUF_SIZE>, &[u8]) -> Result<(), NoteError>,
+    ) -> Result<(), NoteError> {
+        if !matches!(self.note.proto.state, NoteState::Poll(_)) {
+            error!("stream called when response has already been read from");
+            return Err(NoteError::WrongState);
+        }
+
+        let mut chunk = [0u8; READ_LENGTH_MAX + 2];
+        let chunk = &mut chunk[..self.note.proto.read_length + 2];
+        let mut check = crc::Stream::new();
+        let mut timer = Timer::new(&mut self.note.clock);
+
+        self.note.proto.buf.clear();
+
+        loop {
+            let (bytes, done) = self.note.poll_chunk(chunk).await?;
+            check.update(bytes);
+            f(&mut self.note.proto.buf, bytes)?;
+
+            if done {
+                break;
+            }
+
+            delay.delay_ms(RESPONSE_DELAY as u32).await;
+            timer.delayed(RESPONSE_DELAY.into());
+
+            if timer.elapsed(&mut self.note.clock) >= self.timeout {
+                error!("response timed out (>= {}).", self.timeout);
+                return Err(NoteError::TimeOut);
+            }
+        }
+
+        if let Some(seq) = self.note.proto.response_seq() {
+            check.check(seq)?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the response and pass it to `sink` as it is read, see
+    /// [`FutureResponse::wait_stream`](crate::FutureResponse::wait_stream).
+    pub async fn wait_stream(
+        mut self,
+        delay: &mut impl DelayNs,
+        sink: &mut impl Sink,
+    ) -> Result<(), NoteError> {
+        self.stream(delay, |buf, bytes| {
+            // The start of the response is kept to detect an error response.
+            let n = (BUF_SIZE - buf.len()).min(bytes.len());
+            buf.extend_from_slice(&bytes[..n]).ok();
+
+            sink.write(bytes)
+        })
+        .await?;
+
+        let proto = &self.note.proto;
+        if is_error_response(&proto.buf) {
+            Err(notecard_error(&proto.buf).in_request(&proto.request))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait for the response and decode its base64 `payload` into `sink` as it is read, see
+    /// [`FutureResponse::wait_payload`](crate::FutureResponse::wait_payload).
+    pub async fn wait_payload(
+        mut self,
+        delay: &mut impl DelayNs,
+        sink: &mut impl Sink,
+    ) -> Result<T, NoteError> {
+        let mut payload = Base64Field::new("payload", sink);
+        self.stream(delay, |buf, bytes| payload.feed(bytes, buf))
+            .await
+            .map_err(|e| e.in_request(&self.note.proto.request))?;
+
+        // The CRC was checked on the full response.
+        let proto = &self.note.proto;
+        deserialize_response(&proto.buf, None).map_err(|e| e.in_request(&proto.request))
+    }
+
+    /// Wait for response and return deserialized object. Idempotent requests are sent again on
+    /// transient errors, see [`FutureResponse::wait`](crate::FutureResponse::wait).
+    pub async fn wait(self, delay: &mut impl DelayNs) -> Result<T, NoteError> {
+        self.wait_on(delay, None::<&mut NoAttn>).await
+    }
+
+    /// Wait for response like [`wait`](Self::wait), but sleep until `attn` is asserted when the
+    /// response is not ready yet, see [`FutureResponse::wait_attn`](crate::FutureResponse::wait_attn).
+    pub async fn wait_attn(
+        self,
+        delay: &mut impl DelayNs,
+        attn: &mut impl AsyncAttn,
+    ) -> Result<T, NoteError> {
+        self.wait_on(delay, Some(attn)).await
+    }
+
+    /// Wait for the response on `attn`, or on the ATTN of the driver.
+    async fn wait_on(
+        mut self,
+        delay: &mut impl DelayNs,
+        mut attn: Option<&mut impl AsyncAttn>,
+    ) -> Result<T, NoteError> {
+        let mut timer = Timer::new(&mut self.note.clock);
+
+        loop {
+            let e = match self.poll().await {
+                Ok(Some(r)) => return Ok(r),
+                Ok(None) if timer.elapsed(&mut self.note.clock) < self.timeout => {
+                    let remaining = timer.remaining(&mut self.note.clock, self.timeout);
+                    let remaining = remaining.min(self.note.proto.attn_poll);
+                    let waited = match attn.as_deref_mut() {
+                        Some(attn) => attn.wait(delay, remaining).await,
+                        None => self.note.attn.wait(delay, remaining).await,
+                    };
+                    timer.delayed(waited);
+
+                    // Without ATTN, or while it stays asserted, the Notecard is polled.
+                    if waited == 0 {
+                        delay.delay_ms(RESPONSE_DELAY as u32).await;
+                        timer.delayed(RESPONSE_DELAY.into());
+                    }
+                    continue;
+                }
+                Ok(None) => {
+                    error!("response timed out (>= {}).", self.timeout);
+                    NoteError::TimeOut
+                }
+                Err(e) => e,
+            };
+
+            self.note.retry(delay, e, self.request.as_ref()).await?;
+            timer = Timer::new(&mut self.note.clock);
+        }
+    }
+
+    /// Wait for response with a timeout (ms), see [`wait`](Self::wait).
+    pub async fn wait_timeout(
+        self,
+        delay: &mut impl DelayNs,
+        timeout: u32,
+    ) -> Result<T, NoteError> {
+        self.timeout(timeout).wait(delay).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, Fault};
+    use embassy_futures::block_on;
+    use embedded_hal_1::i2c::{ErrorType, I2c as _, Operation};
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use serde_json::{json, Value};
+
+    struct NoopDelay;
+
+    impl DelayNs for NoopDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// An initialized driver for `emu`, with the default configuration changed by `config`.
+    fn notecard<const BS: usize>(
+        emu: &Emulator,
+        config: impl FnOnce(&mut NotecardConfig),
+    ) -> AsyncNotecard<I2c<Emulator>, BS> {
+        let mut c = NotecardConfig::default();
+        config(&mut c);
+
+        let mut note = AsyncNotecard::new(I2c::new(emu.clone(), c.i2c_addr), c);
+        block_on(note.initialize(&mut NoopDelay)).unwrap();
+        note
+    }
+
+    /// Request `card.status`.
+    async fn status<const BS: usize>(
+        note: &mut AsyncNotecard<I2c<Emulator>, BS>,
+    ) -> Result<(), NoteError> {
+        let mut delay = NoopDelay;
+        note.card()
+            .status(&mut delay)
+            .await?
+            .wait(&mut delay)
+            .await?;
+        Ok(())
+    }
+
+    /// Check that the driver recovers with the next request.
+    fn recovers<const BS: usize>(note: &mut AsyncNotecard<I2c<Emulator>, BS>, emu: &Emulator) {
+        block_on(status(note)).unwrap();
+        assert!(matches!(note.proto.state, NoteState::Request));
+        assert_eq!(emu.pending(), 0);
+    }
+
+    /// ATTN that is never asserted, recording the time waited for it.
+    struct Never(std::vec::Vec<u32>);
+
+    impl AsyncAttn for Never {
+        async fn wait(&mut self, _delay: &mut impl DelayNs, timeout: u32) -> u32 {
+            self.0.push(timeout);
+            timeout
+        }
+    }
+
+    /// Blocking mock used as an async bus.
+    struct AsyncMock(Mock);
+
+    impl ErrorType for AsyncMock {
+        type Error = <Mock as ErrorType>::Error;
+    }
+
+    impl embedded_hal_async::i2c::I2c for AsyncMock {
+        async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.read(address, read)
+        }
+
+        async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+            self.0.write(address, write)
+        }
+
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.0.transaction(address, operations)
+        }
+    }
+
+    #[test]
+    fn request_response() {
+        let mut req = b"{\"req\":\"card.status\"}\n".to_vec();
+        req.insert(0, 22);
+
+        let res = b"{\"status\":\"{normal}\",\"storage\":8}\r\n";
+        let mut read = vec![0, res.len() as u8];
+        read.extend_from_slice(res);
+
+        let exp = [
+            // handshake
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![0, 0]),
+            // request
+            Transaction::write(0x17, req),
+            // response
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![0, 0]),
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![res.len() as u8, 0]),
+            Transaction::write(0x17, vec![0, res.len() as u8]),
+            Transaction::read(0x17, read),
+        ];
+
+        let i2c = AsyncMock(Mock::new(&exp));
+        let mut c: AsyncNotecard<I2c<AsyncMock>> =
+            AsyncNotecard::new(I2c::new(i2c, 0x17), NotecardConfig::default());
+        let mut delay = NoopDelay;
+
+        let status = embassy_futures::block_on(async {
+            c.initialize(&mut delay).await?;
+            c.card().status(&mut delay).await?.wait(&mut delay).await
+        })
+        .unwrap();
+        assert_eq!(status.status, "{normal}");
+        assert_eq!(status.storage, 8);
+
+        c.free().free().0.done();
+    }
+
+    #[test]
+    fn retry() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_> = notecard(&emu, |c| c.retry.retries = 2);
+        let mut delay = NoopDelay;
+
+        let mut n = 0;
+        emu.on("hub.get", move |_| {
+            n += 1;
+            match n {
+                1 => json!({ "err": "i2c: corrupted {io}" }),
+                _ => json!({ "device": "dev:1" }),
+            }
+        });
+
+        block_on(async {
+            // While sending.
+            emu.inject(Fault::Nak(1));
+            status(&mut note).await.unwrap();
+            assert_eq!(emu.requests().len(), 1);
+
+            // While waiting.
+            let f = note.card().status(&mut delay).await.unwrap();
+            emu.inject(Fault::Nak(1));
+            f.wait(&mut delay).await.unwrap();
+            assert_eq!(emu.requests().len(), 3);
+
+            // After an `{io}` error response.
+            let hub = note.hub().get(&mut delay).await.unwrap();
+            let hub = hub.wait(&mut delay).await.unwrap();
+            assert_eq!(hub.device.as_deref(), Some("dev:1"));
+            assert_eq!(emu.requests().len(), 5);
+
+            // Not requests that change state.
+            let body = json!({ "temp": 1 });
+            let f = note
+                .note()
+                .add(&mut delay, Some("data.qo"), None, Some(body), None, false)
+                .await
+                .unwrap();
+            emu.inject(Fault::Nak(1));
+            let r = f.wait(&mut delay).await;
+            assert!(matches!(r, Err(NoteError::I2cWriteError(_))));
+            assert_eq!(emu.requests().len(), 6);
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn crc() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_> = notecard(&emu, |c| c.crc = true);
+
+        // The emulator answers with an error if the CRC of the request does not match.
+        block_on(async {
+            status(&mut note).await.unwrap();
+            status(&mut note).await.unwrap();
+            assert_eq!(note.proto.seq, 2);
+
+            // A response to an earlier request.
+            emu.inject(Fault::Garbage(
+                b"{\"status\":\"{normal}\",\"crc\":\"0001:00000000\"}\r\n".to_vec(),
+            ));
+            let r = status(&mut note).await;
+            assert!(matches!(r, Err(NoteError::CrcError)));
+            assert!(matches!(note.proto.state, NoteState::Request));
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn attn_not_asserted() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_> = notecard(&emu, |c| c.attn_poll = Some(100));
+        let mut delay = NoopDelay;
+        let mut attn = Never(std::vec::Vec::new());
+
+        block_on(async {
+            // A response that is ready is read without waiting on ATTN, which is not re-armed.
+            let f = note.card().status(&mut delay).await.unwrap();
+            f.wait_attn(&mut delay, &mut attn).await.unwrap();
+            assert!(attn.0.is_empty());
+
+            // The Notecard is polled every `attn_poll` ms.
+            emu.inject(Fault::Slow(2));
+            let f = note.card().status(&mut delay).await.unwrap();
+            f.wait_attn(&mut delay, &mut attn).await.unwrap();
+            assert_eq!(attn.0, [100, 100]);
+            assert_eq!(emu.requests().len(), 2);
+
+            // Until the response times out.
+            attn.0.clear();
+            emu.inject(Fault::Slow(10));
+            let f = note.card().status(&mut delay).await.unwrap();
+            let r = f.timeout(250).wait_attn(&mut delay, &mut attn).await;
+            assert!(matches!(r, Err(NoteError::TimeOut)));
+            assert_eq!(attn.0, [100, 100, 50]);
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn stream() {
+        let emu = Emulator::new();
+        let data: std::vec::Vec<u8> = (0..3000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut b64 = [0u8; 4096];
+        let sz = base64::encode_config_slice(&data, base64::STANDARD, &mut b64);
+        let b64 = std::string::String::from_utf8(b64[..sz].to_vec()).unwrap();
+        emu.on("dfu.get", move |_| json!({ "payload": b64 }));
+
+        let status = "x".repeat(1000);
+        emu.respond("hub.sync.status", &json!({ "status": status }).to_string());
+
+        let mut note: AsyncNotecard<_, 256> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay;
+
+        block_on(async {
+            let mut firmware: Vec<u8, 4096> = Vec::new();
+            let f = note.dfu().get::<0>(&mut delay, data.len(), Some(0));
+            let r = f.await.unwrap();
+            let r = r.wait_payload(&mut delay, &mut firmware).await.unwrap();
+            assert_eq!(r.payload, "");
+            assert_eq!(&firmware[..], &data[..]);
+
+            let mut response = std::vec::Vec::new();
+            let f = note.hub().sync_status(&mut delay).await.unwrap();
+            f.wait_stream(&mut delay, &mut |bytes: &[u8]| {
+                response.extend_from_slice(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+            let response: Value = serde_json::from_slice(&response).unwrap();
+            assert_eq!(response["status"], status.as_str());
+
+            // Error responses.
+            let f = note
+                .note()
+                .get::<()>(&mut delay, "missing.db", "1", false, false);
+            let r = f.await.unwrap();
+            let r = r.wait_stream(&mut delay, &mut Vec::<u8, 256>::new()).await;
+            assert!(matches!(r, Err(NoteError::NotecardErr(_))));
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn commands() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay;
+
+        block_on(async {
+            note.hub()
+                .sync_cmd(&mut delay, true, None, None)
+                .await
+                .unwrap();
+            assert!(matches!(note.proto.state, NoteState::Request));
+            assert_eq!(emu.requests()[0]["cmd"], "hub.sync");
+            assert_eq!(emu.pending(), 0);
+
+            let body = json!({ "temp": 1 });
+            note.note()
+                .add_cmd(
+                    &mut delay,
+                    Some("data.qo"),
+                    Some("t"),
+                    Some(body),
+                    None,
+                    false,
+                )
+                .await
+                .unwrap();
+            assert_eq!(emu.notes("data.qo")["t"]["body"]["temp"], 1);
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn request_larger_than_buffer() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_, 128> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay;
+
+        let payload = "QUJD".repeat(256);
+        block_on(async {
+            // The emulator answers with an error if the CRC does not match.
+            note.note()
+                .add(
+                    &mut delay,
+                    Some("big.qo"),
+                    None,
+                    None::<()>,
+                    Some(&payload),
+                    false,
+                )
+                .await
+                .unwrap()
+                .wait(&mut delay)
+                .await
+                .unwrap();
+            assert_eq!(emu.requests().last().unwrap()["payload"], payload.as_str());
+
+            note.note()
+                .add_cmd(
+                    &mut delay,
+                    Some("big.qo"),
+                    Some("t"),
+                    None::<()>,
+                    Some(&payload),
+                    false,
+                )
+                .await
+                .unwrap();
+            assert_eq!(emu.notes("big.qo")["t"]["payload"], payload.as_str());
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn response_overflow() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_, 128> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay;
+
+        block_on(async {
+            let f = note.card().version(&mut delay).await.unwrap();
+            assert!(matches!(
+                f.wait(&mut delay).await,
+                Err(NoteError::BufOverflow)
+            ));
+            assert!(matches!(note.proto.state, NoteState::Request));
+            assert_eq!(emu.pending(), 0);
+        });
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn dropped_response() {
+        let emu = Emulator::new();
+        let mut note: AsyncNotecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay;
+
+        // Dropped before the response is ready.
+        emu.inject(Fault::Slow(2));
+        drop(block_on(note.card().version(&mut delay)).unwrap());
+        assert!(matches!(note.proto.state, NoteState::Poll(_)));
+        recovers(&mut note, &emu);
+
+        // Dropped while reading the response.
+        block_on(async {
+            let mut version = note.card().version(&mut delay).await.unwrap();
+            assert!(version.poll().await.unwrap().is_none());
+        });
+        assert!(matches!(note.proto.state, NoteState::Response(_)));
+        recovers(&mut note, &emu);
+
+        // Timed out, with the response arriving late.
+        emu.inject(Fault::Slow(10));
+        block_on(async {
+            let f = note.card().version(&mut delay).await.unwrap();
+            let r = f.timeout(100).wait(&mut delay).await;
+            assert!(matches!(r, Err(NoteError::TimeOut)));
+        });
+        recovers(&mut note, &emu);
+    }
+}
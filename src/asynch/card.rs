@@ -0,0 +1,255 @@
+//! https://dev.blues.io/reference/notecard-api/card-requests/
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::card::{req, res, GpioMode, Transport};
+use crate::{str_string, Clock, NoteError};
+
+pub struct Card<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> Card<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> Card<'_, IOM, BS, C, A> {
+        Card { note }
+    }
+
+    /// Retrieves current date and time information. Upon power-up, the Notecard must complete a
+    /// sync to Notehub in order to obtain time and location data. Before the time is obtained,
+    /// this request will return `{"zone":"UTC,Unknown"}`. If that comes with an `err`, the zone is
+    /// available from the driver, see [`AsyncNotecard::error_data`](crate::asynch::AsyncNotecard::error_data).
+    pub async fn time(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Time, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"card.time\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Returns general information about the Notecard's operating status.
+    pub async fn status(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"card.status\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Performs a firmware restart of the Notecard.
+    pub async fn restart(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request_raw(delay, b"{\"req\":\"card.restart\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Retrieves the current location of the Notecard.
+    pub async fn location(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Location, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"card.location\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Sets location-related configuration settings. Retrieves the current location mode when passed with no argument.
+    pub async fn location_mode(
+        self,
+        delay: &mut impl DelayNs,
+        mode: Option<&str>,
+        seconds: Option<u32>,
+        vseconds: Option<&str>,
+        delete: Option<bool>,
+        max: Option<u32>,
+        lat: Option<f32>,
+        lon: Option<f32>,
+        minutes: Option<u32>,
+    ) -> Result<AsyncFutureResponse<'a, res::LocationMode, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::LocationMode {
+                    mode: str_string(mode)?,
+                    seconds,
+                    vseconds: str_string(vseconds)?,
+                    delete,
+                    max,
+                    lat,
+                    lon,
+                    minutes,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Store location data in a Notefile at the `periodic` interval, or using specified `heartbeat`.
+    /// Only available when `card.location.mode` has been set to `periodic`.
+    pub async fn location_track(
+        self,
+        delay: &mut impl DelayNs,
+        start: bool,
+        heartbeat: bool,
+        sync: bool,
+        hours: Option<i32>,
+        file: Option<&str>,
+    ) -> Result<AsyncFutureResponse<'a, res::LocationTrack, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::LocationTrack {
+                    start: start.then_some(true),
+                    stop: (!start).then_some(true),
+                    heartbeat: heartbeat.then_some(true),
+                    sync: sync.then_some(true),
+                    hours,
+                    file: str_string(file)?,
+                },
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    pub async fn wireless(
+        self,
+        delay: &mut impl DelayNs,
+        mode: Option<&str>,
+        apn: Option<&str>,
+        method: Option<&str>,
+        hours: Option<u32>,
+    ) -> Result<AsyncFutureResponse<'a, res::Wireless, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Wireless {
+                    mode: str_string(mode)?,
+                    method: str_string(method)?,
+                    apn: str_string(apn)?,
+                    hours,
+                },
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Returns firmware version information for the Notecard.
+    pub async fn version(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Version, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"card.version\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Configure Notecard Outboard Firmware Update feature
+    /// Added in v3.5.1 Notecard Firmware.
+    pub async fn dfu(
+        self,
+        delay: &mut impl DelayNs,
+        name: Option<req::DFUName>,
+        on: Option<bool>,
+        stop: Option<bool>,
+    ) -> Result<AsyncFutureResponse<'a, res::DFU, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(delay, req::DFU::new(name, on, stop))
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    pub async fn transport(
+        self,
+        delay: &mut impl DelayNs,
+        method: Transport,
+        allow: Option<bool>,
+        umin: Option<bool>,
+        seconds: Option<u32>,
+    ) -> Result<AsyncFutureResponse<'a, res::Transport, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Transport {
+                    method: method.str(),
+                    allow,
+                    umin,
+                    seconds,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Turn AUX pins off.
+    ///
+    /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-aux
+    pub async fn aux_off(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Aux, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request_raw(delay, b"{\"req\":\"card.aux\", \"mode\":\"off\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Configure AUX ports to act as GPIOs.
+    ///
+    /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-aux
+    pub async fn aux_gpio(
+        self,
+        delay: &mut impl DelayNs,
+        aux1: GpioMode,
+        aux2: GpioMode,
+        aux3: GpioMode,
+        aux4: GpioMode,
+    ) -> Result<AsyncFutureResponse<'a, res::Aux, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Aux {
+                    mode: "gpio",
+                    usage: [aux1.str(), aux2.str(), aux3.str(), aux4.str()],
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Configure the ATTN pin, see [`Card::attn`](crate::card::Card::attn).
+    pub async fn attn(
+        self,
+        delay: &mut impl DelayNs,
+        mode: Option<&str>,
+        seconds: Option<u32>,
+    ) -> Result<AsyncFutureResponse<'a, res::Attn, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Attn {
+                    mode: str_string(mode)?,
+                    seconds,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+}
@@ -0,0 +1,59 @@
+//! https://dev.blues.io/api-reference/notecard-api/dfu-requests/
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::dfu::{req, res};
+use crate::{Clock, NoteError};
+
+pub struct DFU<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> DFU<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> DFU<'_, IOM, BS, C, A> {
+        DFU { note }
+    }
+
+    /// Retrieves downloaded firmware data from the Notecard.
+    /// Note: this request is functional only when the Notecard has been set to
+    /// dfu mode with a `hub.set`, `mode:dfu` request.
+    pub async fn get<const PS: usize>(
+        self,
+        delay: &mut impl DelayNs,
+        length: usize,
+        offset: Option<usize>,
+    ) -> Result<AsyncFutureResponse<'a, res::Get<PS>, IOM, BS, C, A, req::Get<PS>>, NoteError> {
+        let request = self
+            .note
+            .request(delay, req::Get::<PS> { length, offset })
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Gets and sets the background download status of MCU host or Notecard
+    /// firmware updates.
+    pub async fn status(
+        self,
+        delay: &mut impl DelayNs,
+        name: Option<req::StatusName>,
+        stop: Option<bool>,
+        status: Option<&str>,
+        version: Option<&str>,
+        vvalue: Option<&str>, // This is not JSON :(
+        on: Option<bool>,
+        err: Option<&str>,
+    ) -> Result<AsyncFutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Status::new(name, stop, status, version, vvalue, on, err),
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+}
@@ -0,0 +1,153 @@
+//! <https://dev.blues.io/reference/notecard-api/hub-requests/>
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::hub::{req, res};
+use crate::{Clock, NoteError};
+
+pub struct Hub<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> Hub<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> Hub<'_, IOM, BS, C, A> {
+        Hub { note }
+    }
+
+    /// Add a "device health" log message to send to Notehub on the next sync.
+    pub async fn log(
+        self,
+        delay: &mut impl DelayNs,
+        text: &str,
+        alert: bool,
+        sync: bool,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(delay, req::HubLog { text, alert, sync })
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Like [`log`](Self::log), but sent as a command without waiting for a response.
+    pub async fn log_cmd(
+        self,
+        delay: &mut impl DelayNs,
+        text: &str,
+        alert: bool,
+        sync: bool,
+    ) -> Result<(), NoteError> {
+        self.note
+            .command(delay, req::HubLog { text, alert, sync })
+            .await
+    }
+
+    /// The [hub.get](https://dev.blues.io/api-reference/notecard-api/hub-requests/#hub-get) request
+    /// retrieves the current Notehub configuration for the Natecard.
+    pub async fn get(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Hub, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"hub.get\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// The [hub.set](https://dev.blues.io/reference/notecard-api/hub-requests/#hub-set) request is
+    /// the primary method for controlling the Notecard's Notehub connection and sync behavior.
+    pub async fn set(
+        self,
+        delay: &mut impl DelayNs,
+        product: Option<&str>,
+        host: Option<&str>,
+        mode: Option<req::HubMode>,
+        sn: Option<&str>,
+        outbound: Option<u32>,
+        duration: Option<u32>,
+        voutbound: Option<&str>,
+        inbound: Option<u32>,
+        vinbound: Option<&str>,
+        align: Option<bool>,
+        sync: Option<bool>,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::HubSet {
+                    product,
+                    host,
+                    mode,
+                    sn,
+                    outbound,
+                    duration,
+                    voutbound,
+                    inbound,
+                    vinbound,
+                    align,
+                    sync,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Manually initiates a sync with Notehub. `allow` can be specified to `true` to
+    /// remove the notecard from any penalty boxes.
+    pub async fn sync(
+        self,
+        delay: &mut impl DelayNs,
+        allow: bool,
+        out: Option<bool>,
+        inn: Option<bool>,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::HubSync {
+                    allow: if allow { Some(true) } else { None },
+                    out,
+                    inn,
+                },
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Like [`sync`](Self::sync), but sent as a command without waiting for a response. Use
+    /// [`sync_status`](Self::sync_status) to check on the sync.
+    pub async fn sync_cmd(
+        self,
+        delay: &mut impl DelayNs,
+        allow: bool,
+        out: Option<bool>,
+        inn: Option<bool>,
+    ) -> Result<(), NoteError> {
+        self.note
+            .command(
+                delay,
+                req::HubSync {
+                    allow: if allow { Some(true) } else { None },
+                    out,
+                    inn,
+                },
+            )
+            .await
+    }
+
+    /// Check on the status of a recently triggered or previous sync.
+    pub async fn sync_status(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::SyncStatus, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"hub.sync.status\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+}
@@ -0,0 +1,62 @@
+//! Serial-over-I2C: <https://dev.blues.io/notecard/notecard-guides/serial-over-i2c-protocol/>
+
+use embedded_hal_async::i2c::{self, Error as _};
+
+use super::AsyncTransport;
+use crate::transport::i2c::{self as proto, I2cBus};
+use crate::NoteError;
+
+/// The Notecard connected over an async I2C bus.
+pub struct I2c<IOM: i2c::I2c> {
+    i2c: IOM,
+    addr: u8,
+}
+
+impl<IOM: i2c::I2c> I2c<IOM> {
+    pub fn new(i2c: IOM, addr: u8) -> I2c<IOM> {
+        I2c { i2c, addr }
+    }
+
+    /// Free the I2C device.
+    pub fn free(self) -> IOM {
+        self.i2c
+    }
+
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+}
+
+impl<IOM: i2c::I2c> I2cBus for I2c<IOM> {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self.i2c
+            .write(self.addr, bytes)
+            .await
+            .map_err(|e| NoteError::I2cWriteError(e.kind().into()))
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), NoteError> {
+        self.i2c
+            .read(self.addr, buf)
+            .await
+            .map_err(|e| NoteError::I2cReadError(e.kind().into()))
+    }
+}
+
+impl<IOM: i2c::I2c> AsyncTransport for I2c<IOM> {
+    async fn ping(&mut self) -> bool {
+        I2cBus::write(self, &[]).await.is_ok()
+    }
+
+    async fn data_query(&mut self) -> Result<usize, NoteError> {
+        proto::data_query(self).await
+    }
+
+    async fn read(&mut self, avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError> {
+        proto::read(self, avail, buf).await
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        proto::write(self, chunk).await
+    }
+}
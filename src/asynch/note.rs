@@ -0,0 +1,203 @@
+//! <https://dev.blues.io/reference/notecard-api/note-requests/>
+
+use core::marker::PhantomData;
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::note::{req, res, TemplateFormat};
+use crate::{str_string, Clock, NoteError};
+
+pub struct Note<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> Note<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> Note<'_, IOM, BS, C, A> {
+        Note { note }
+    }
+
+    /// Adds a note to a notefile, creating the Notefile if it doesn't yet exist. When sending this
+    /// request to the Notecard, if a Notefile name is specified, the file must either be a DB
+    /// Notefile or outbound queue file (.qo/.qos). When sending this request to Notehub, the file
+    /// must either be a DB Notefile or an inbound queue file (.qi/.qis).
+    ///
+    /// If you don't use a template the size of the payload is maximum 250 bytes, with a template 8KB
+    /// seems to work.
+    pub async fn add<T: Serialize + Default>(
+        self,
+        delay: &mut impl DelayNs,
+        file: Option<&str>,
+        note: Option<&str>,
+        body: Option<T>,
+        payload: Option<&str>,
+        sync: bool,
+    ) -> Result<AsyncFutureResponse<'a, res::Add, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Add::<T> {
+                    file: str_string(file)?,
+                    note: str_string(note)?,
+                    body,
+                    payload,
+                    sync: Some(sync),
+                    ..<req::Add<T> as Default>::default()
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Like [`add`](Self::add), but sent as a command without waiting for a response. Errors
+    /// adding the note are not reported.
+    pub async fn add_cmd<T: Serialize + Default>(
+        self,
+        delay: &mut impl DelayNs,
+        file: Option<&str>,
+        note: Option<&str>,
+        body: Option<T>,
+        payload: Option<&str>,
+        sync: bool,
+    ) -> Result<(), NoteError> {
+        self.note
+            .command(
+                delay,
+                req::Add::<T> {
+                    file: str_string(file)?,
+                    note: str_string(note)?,
+                    body,
+                    payload,
+                    sync: Some(sync),
+                    ..<req::Add<T> as Default>::default()
+                },
+            )
+            .await
+    }
+
+    /// Updates a Note in a DB Notefile by its ID, replacing the existing body and/or payload.
+    pub async fn update<T: Serialize + Default>(
+        self,
+        delay: &mut impl DelayNs,
+        file: &str,
+        note: &str,
+        body: Option<T>,
+        payload: Option<&str>,
+        verify: bool,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Update::<T> {
+                    file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
+                    note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
+                    body,
+                    payload,
+                    verify,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Retrieves a Note from a Notefile.
+    ///
+    /// * When sending this request to the Notecard, the file must either be a DB Notefile (.db or .dbx) or inbound queue file (.qi/.qis).
+    /// * When sending this request to Notehub, the file must be a DB Notefile (.db).
+    ///
+    /// .qo/.qos Notes must be read from the Notehub event table using the Notehub Event API.
+    pub async fn get<T: DeserializeOwned + Serialize>(
+        self,
+        delay: &mut impl DelayNs,
+        file: &str,
+        note: &str,
+        delete: bool,
+        deleted: bool,
+    ) -> Result<AsyncFutureResponse<'a, res::Get<T>, IOM, BS, C, A, req::Get<T>>, NoteError> {
+        let request = self
+            .note
+            .request(
+                delay,
+                req::Get::<T> {
+                    file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
+                    note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
+                    delete,
+                    deleted,
+                    body: PhantomData,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Deletes Notefiles from a DB Notefile by its Note ID. To delete Notes from a .qi Notefile, use note.get or note.changes with delete:true.
+    pub async fn delete(
+        self,
+        delay: &mut impl DelayNs,
+        file: &str,
+        note: &str,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Delete {
+                    file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
+                    note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
+                    verify: false,
+                },
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Using the note.template request command with any .qo/.qos Notefile, developers can provide
+    /// the Notecard with a schema of sorts to apply to future Notes added to the Notefile. This
+    /// template acts as a hint to the Notecard that allows it to internally store data as
+    /// fixed-length binary records rather than as flexible JSON objects which require much more
+    /// memory. Using templated Notes in place of regular Notes increases the storage and sync
+    /// capability of the Notecard by an order of magnitude.
+    ///
+    /// See
+    /// https://dev.blues.io/notecard/notecard-walkthrough/low-bandwidth-design/#understanding-template-data-types
+    /// for the format and values of the template.
+    pub async fn template<T: Serialize + Default>(
+        self,
+        delay: &mut impl DelayNs,
+        file: Option<&str>,
+        body: Option<T>,
+        length: Option<u32>,
+        format: TemplateFormat,
+        port: Option<u32>,
+        delete: Option<bool>,
+    ) -> Result<AsyncFutureResponse<'a, res::Template, IOM, BS, C, A>, NoteError> {
+        if let Some(port) = port
+            && !(1..=100).contains(&port)
+        {
+            return Err(NoteError::InvalidRequest);
+        }
+
+        let format = match format {
+            TemplateFormat::Default => None,
+            TemplateFormat::Compact => Some("compact"),
+        };
+
+        self.note
+            .request(
+                delay,
+                req::Template::<T> {
+                    file: str_string(file)?,
+                    body,
+                    length,
+                    format: str_string(format)?,
+                    port,
+                    delete,
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+}
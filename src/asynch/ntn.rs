@@ -0,0 +1,62 @@
+//! https://dev.blues.io/api-reference/notecard-api/ntn-requests/
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::ntn::{req, res, NtnSetGps};
+use crate::{Clock, NoteError};
+
+pub struct NTN<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> NTN<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> NTN<'_, IOM, BS, C, A> {
+        NTN { note }
+    }
+
+    /// Once a Notecard is connected to a Starnote device, the presence of a physical Starnote is stored in a permanent configuration that is not affected by a card.restore request. This request clears this configuration and allows you to return to testing NTN mode over cellular or Wi-Fi.
+    pub async fn reset<const PS: usize>(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request_raw(delay, b"{\"req\":\"ntn.reset\"}\n")
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+
+    /// Gets and sets the background download status of MCU host or Notecard
+    /// firmware updates.
+    pub async fn status(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<AsyncFutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
+            .request_raw(delay, b"{\"req\":\"ntn.status\"}\n")
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note).keep(request))
+    }
+
+    /// Determines whether a Notecard should override a paired Starnote's GPS/GNSS location with its own GPS/GNSS location. The paired Starnote uses its own GPS/GNSS location by default.
+    pub async fn gps(
+        self,
+        delay: &mut impl DelayNs,
+        gps: Option<NtnSetGps>,
+    ) -> Result<AsyncFutureResponse<'a, res::Gps, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Gps {
+                    on: gps.map(|g| matches!(g, NtnSetGps::Notecard)),
+                    off: gps.map(|g| matches!(g, NtnSetGps::Starnote)),
+                },
+            )
+            .await?;
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+}
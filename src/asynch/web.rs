@@ -0,0 +1,57 @@
+//! <https://dev.blues.io/api-reference/notecard-api/web-requests>
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal_async::delay::DelayNs;
+use serde::Serialize;
+
+use super::{AsyncAttn, AsyncFutureResponse, AsyncNotecard, AsyncTransport};
+use crate::web::{req, res};
+use crate::{str_string, Clock, NoteError};
+
+pub struct Web<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> {
+    note: &'a mut AsyncNotecard<IOM, BS, C, A>,
+}
+
+impl<'a, IOM: AsyncTransport, const BS: usize, C: Clock, A: AsyncAttn> Web<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut AsyncNotecard<IOM, BS, C, A>) -> Web<'_, IOM, BS, C, A> {
+        Web { note }
+    }
+
+    /// Performs a simple HTTP or HTTPS POST request against an external endpoint, and returns the response to the Notecard.
+    ///
+    /// The response is waited for until the Notecard gives up on the request after `seconds`
+    /// (90 s by default), unless the `response_timeout` of the driver is longer.
+    pub async fn post<T: Serialize + Default>(
+        self,
+        delay: &mut impl DelayNs,
+        route: &str,
+        name: Option<&str>,
+        body: Option<T>,
+        payload: Option<&str>,
+        content: Option<&str>,
+        seconds: Option<u16>,
+        max: Option<u16>,
+        verify: Option<bool>,
+        nasync: Option<bool>,
+    ) -> Result<AsyncFutureResponse<'a, res::Post, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(
+                delay,
+                req::Post::<T> {
+                    route: heapless::String::try_from(route).map_err(NoteError::string_err)?,
+                    name: str_string(name)?,
+                    body,
+                    payload,
+                    content: str_string(content)?,
+                    seconds,
+                    max,
+                    verify,
+                    nasync,
+                },
+            )
+            .await?;
+
+        Ok(AsyncFutureResponse::from(self.note))
+    }
+}
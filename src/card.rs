@@ -2,14 +2,13 @@
 
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{Deserialize, Serialize};
 
-use super::{str_string, FutureResponse, NoteError, Notecard};
+use super::{str_string, Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport;
 
-pub struct Card<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+pub struct Card<'a, IOM: transport::Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
 /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-transport
@@ -63,37 +62,39 @@ impl GpioMode {
     }
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> Card<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> Card<'_, IOM, BS> {
+impl<'a, IOM: transport::Transport, const BS: usize, C: Clock, A: Attn> Card<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> Card<'_, IOM, BS, C, A> {
         Card { note }
     }
 
     /// Retrieves current date and time information. Upon power-up, the Notecard must complete a
     /// sync to Notehub in order to obtain time and location data. Before the time is obtained,
-    /// this request will return `{"zone":"UTC,Unknown"}`.
+    /// this request will return `{"zone":"UTC,Unknown"}`. If that comes with an `err`, the zone is
+    /// available from the driver, see [`Notecard::error_data`](crate::Notecard::error_data).
     pub fn time(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Time, IOM, BS>, NoteError> {
-        self.note.request_raw(delay, b"{\"req\":\"card.time\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Time, IOM, BS, C, A>, NoteError> {
+        let request = self.note.request_raw(delay, b"{\"req\":\"card.time\"}\n")?;
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Returns general information about the Notecard's operating status.
     pub fn status(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Status, IOM, BS>, NoteError> {
-        self.note
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
             .request_raw(delay, b"{\"req\":\"card.status\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Performs a firmware restart of the Notecard.
     pub fn restart(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note
             .request_raw(delay, b"{\"req\":\"card.restart\"}\n")?;
         Ok(FutureResponse::from(self.note))
@@ -102,17 +103,18 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
     /// Retrieves the current location of the Notecard.
     pub fn location(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Location, IOM, BS>, NoteError> {
-        self.note
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Location, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
             .request_raw(delay, b"{\"req\":\"card.location\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Sets location-related configuration settings. Retrieves the current location mode when passed with no argument.
     pub fn location_mode(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         mode: Option<&str>,
         seconds: Option<u32>,
         vseconds: Option<&str>,
@@ -121,11 +123,10 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
         lat: Option<f32>,
         lon: Option<f32>,
         minutes: Option<u32>,
-    ) -> Result<FutureResponse<'a, res::LocationMode, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::LocationMode, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::LocationMode {
-                req: "card.location.mode",
                 mode: str_string(mode)?,
                 seconds,
                 vseconds: str_string(vseconds)?,
@@ -143,17 +144,16 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
     /// Only available when `card.location.mode` has been set to `periodic`.
     pub fn location_track(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         start: bool,
         heartbeat: bool,
         sync: bool,
         hours: Option<i32>,
         file: Option<&str>,
-    ) -> Result<FutureResponse<'a, res::LocationTrack, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::LocationTrack, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::LocationTrack {
-                req: "card.location.track",
                 start: start.then_some(true),
                 stop: (!start).then_some(true),
                 heartbeat: heartbeat.then_some(true),
@@ -168,16 +168,15 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
 
     pub fn wireless(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         mode: Option<&str>,
         apn: Option<&str>,
         method: Option<&str>,
         hours: Option<u32>,
-    ) -> Result<FutureResponse<'a, res::Wireless, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Wireless, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Wireless {
-                req: "card.wireless",
                 mode: str_string(mode)?,
                 method: str_string(method)?,
                 apn: str_string(apn)?,
@@ -191,38 +190,38 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
     /// Returns firmware version information for the Notecard.
     pub fn version(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Version, IOM, BS>, NoteError> {
-        self.note
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Version, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
             .request_raw(delay, b"{\"req\":\"card.version\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Configure Notecard Outboard Firmware Update feature
     /// Added in v3.5.1 Notecard Firmware.
     pub fn dfu(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         name: Option<req::DFUName>,
         on: Option<bool>,
         stop: Option<bool>,
-    ) -> Result<FutureResponse<'a, res::DFU, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::DFU, IOM, BS, C, A>, NoteError> {
         self.note.request(delay, req::DFU::new(name, on, stop))?;
         Ok(FutureResponse::from(self.note))
     }
 
     pub fn transport(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         method: Transport,
         allow: Option<bool>,
         umin: Option<bool>,
         seconds: Option<u32>,
-    ) -> Result<FutureResponse<'a, res::Transport, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Transport, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Transport {
-                req: "card.transport",
                 method: method.str(),
                 allow,
                 umin,
@@ -237,8 +236,8 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
     /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-aux
     pub fn aux_off(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Aux, IOM, BS>, NoteError> {
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Aux, IOM, BS, C, A>, NoteError> {
         self.note.request_raw(delay, b"{\"req\":\"card.aux\", \"mode\":\"off\"}\n")?;
         Ok(FutureResponse::from(self.note))
     }
@@ -248,22 +247,42 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> C
     /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-aux
     pub fn aux_gpio(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         aux1: GpioMode,
         aux2: GpioMode,
         aux3: GpioMode,
         aux4: GpioMode,
-    ) -> Result<FutureResponse<'a, res::Aux, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Aux, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Aux {
-                req: "card.aux",
                 mode: "gpio",
                 usage: [aux1.str(), aux2.str(), aux3.str(), aux4.str()],
             },
         )?;
         Ok(FutureResponse::from(self.note))
     }
+
+    /// Configure the ATTN pin, e.g. `arm,files` with `mode` to assert ATTN when notes arrive, or
+    /// `sleep` with `seconds` to put the host to sleep. Give the pin to the driver with
+    /// [`Notecard::with_attn`](crate::Notecard::with_attn) to wait for responses on ATTN.
+    ///
+    /// https://dev.blues.io/api-reference/notecard-api/card-requests/latest/#card-attn
+    pub fn attn(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        mode: Option<&str>,
+        seconds: Option<u32>,
+    ) -> Result<FutureResponse<'a, res::Attn, IOM, BS, C, A>, NoteError> {
+        self.note.request(
+            delay,
+            req::Attn {
+                mode: str_string(mode)?,
+                seconds,
+            },
+        )?;
+        Ok(FutureResponse::from(self.note))
+    }
 }
 
 pub mod req {
@@ -272,15 +291,31 @@ pub mod req {
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Aux {
-        pub req: &'static str,
         pub mode: &'static str,
         pub usage: [&'static str; 4],
     }
 
+    impl NotecardRequest for Aux {
+        const NAME: &'static str = "card.aux";
+        type Response = res::Aux;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
-    pub struct Transport {
-        pub req: &'static str,
+    pub struct Attn {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub mode: Option<heapless::String<60>>,
 
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub seconds: Option<u32>,
+    }
+
+    impl NotecardRequest for Attn {
+        const NAME: &'static str = "card.attn";
+        type Response = res::Attn;
+    }
+
+    #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
+    pub struct Transport {
         pub method: &'static str,
 
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -294,10 +329,13 @@ pub mod req {
         pub seconds: Option<u32>,
     }
 
+    impl NotecardRequest for Transport {
+        const NAME: &'static str = "card.transport";
+        type Response = res::Transport;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Wireless {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub mode: Option<heapless::String<20>>,
 
@@ -311,10 +349,13 @@ pub mod req {
         pub hours: Option<u32>,
     }
 
+    impl NotecardRequest for Wireless {
+        const NAME: &'static str = "card.wireless";
+        type Response = res::Wireless;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct LocationTrack {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub start: Option<bool>,
 
@@ -334,10 +375,13 @@ pub mod req {
         pub file: Option<heapless::String<20>>,
     }
 
+    impl NotecardRequest for LocationTrack {
+        const NAME: &'static str = "card.location.track";
+        type Response = res::LocationTrack;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct LocationMode {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub mode: Option<heapless::String<20>>,
 
@@ -363,6 +407,11 @@ pub mod req {
         pub minutes: Option<u32>,
     }
 
+    impl NotecardRequest for LocationMode {
+        const NAME: &'static str = "card.location.mode";
+        type Response = res::LocationMode;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, PartialEq)]
     #[serde(rename_all = "lowercase")]
     pub enum DFUName {
@@ -377,8 +426,6 @@ pub mod req {
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format)]
     pub struct DFU {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub name: Option<req::DFUName>,
 
@@ -395,6 +442,11 @@ pub mod req {
         pub start: Option<bool>,
     }
 
+    impl NotecardRequest for DFU {
+        const NAME: &'static str = "card.dfu";
+        type Response = res::DFU;
+    }
+
     impl DFU {
         pub fn new(name: Option<req::DFUName>, on: Option<bool>, stop: Option<bool>) -> Self {
             // The `on`/`off` and `stop`/`start` parameters are exclusive
@@ -403,7 +455,6 @@ pub mod req {
             // This way we are not sending the `on` and `off` parameters together.
             // Same thing applies to the `stop`/`start` parameter.
             Self {
-                req: "card.dfu",
                 name,
                 on: on.and_then(|v| if v { Some(true) } else { None }),
                 off: on.and_then(|v| if v { None } else { Some(true) }),
@@ -430,6 +481,13 @@ pub mod res {
         pub file: Option<heapless::String<20>>,
     }
 
+    #[derive(Deserialize, Debug, defmt::Format)]
+    pub struct Attn {
+        /// Notefiles with changes, when armed with `files`.
+        pub files: Option<heapless::Vec<heapless::String<40>, 8>>,
+        pub set: Option<bool>,
+    }
+
     #[derive(Deserialize, Debug, defmt::Format)]
     pub struct Aux {
         pub mode: Option<heapless::String<20>>,
@@ -732,27 +790,27 @@ mod tests {
     fn test_dfu_req() {
         // Test basic request
         let req = req::DFU::new(None, None, None);
-        let res: heapless::String<1024> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<1024> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"card.dfu"}"#);
 
         // Test name & on request
         let req = req::DFU::new(Some(req::DFUName::Esp32), Some(true), None);
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"card.dfu","name":"esp32","on":true}"#);
 
         // Test off request
         let req = req::DFU::new(None, Some(false), None);
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"card.dfu","off":true}"#);
 
         // Test stop request
         let req = req::DFU::new(None, None, Some(true));
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"card.dfu","stop":true}"#);
 
         // Test start request
         let req = req::DFU::new(None, None, Some(false));
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"card.dfu","start":true}"#);
     }
 
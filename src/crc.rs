@@ -0,0 +1,268 @@
+//! Sequence numbers and CRC32 of requests and responses, used to detect corruption on the bus.
+//!
+//! The field `"crc":"SSSS:CCCCCCCC"` is added as the last field of the JSON object, where `SSSS`
+//! is the sequence number and `CCCCCCCC` the CRC32 of the object without the field, both in hex.
+//! The Notecard answers with the same sequence number and the CRC32 of the response.
+//!
+//! > `note-c`: https://github.com/blues/note-c/blob/master/n_request.c (`crcAdd` and `crcError`)
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use heapless::Vec;
+
+use crate::NoteError;
+
+/// Length of the field `,"crc":"SSSS:CCCCCCCC"` including the leading comma.
+const FIELD_LENGTH: usize = 22;
+
+/// CRC-32 (IEEE 802.3) polynomial, reversed.
+const POLY: u32 = 0xEDB8_8320;
+
+/// CRC32 computed incrementally, for requests that are written while they are serialized.
+#[derive(Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        Crc32(!0)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u32;
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 {
+                    (self.0 >> 1) ^ POLY
+                } else {
+                    self.0 >> 1
+                };
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// CRC32 of the concatenated `parts`.
+pub(crate) fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = Crc32::new();
+
+    for p in parts {
+        crc.update(p);
+    }
+
+    crc.finish()
+}
+
+/// Format the field `"crc":"SSSS:CCCCCCCC"`, without the leading comma.
+pub(crate) fn field(seq: u16, crc: u32) -> [u8; FIELD_LENGTH - 1] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut f = *br#""crc":"SSSS:CCCCCCCC""#;
+
+    for i in 0..4 {
+        f[7 + i] = HEX[(seq >> (12 - 4 * i)) as usize & 0xf];
+    }
+
+    for i in 0..8 {
+        f[12 + i] = HEX[(crc >> (28 - 4 * i)) as usize & 0xf];
+    }
+
+    f
+}
+
+/// Parse hex digits.
+fn hex(digits: &[u8]) -> Option<u32> {
+    core::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| u32::from_str_radix(s, 16).ok())
+}
+
+/// Parse the sequence number and CRC field of a JSON object, returning the sequence number, the
+/// CRC in the field and the CRC of the object without the field. Returns `None` if the object has
+/// no such field.
+pub(crate) fn parse(body: &[u8]) -> Option<(u16, u32, u32)> {
+    let (object, seq, crc) = split(body)?;
+
+    Some((seq, crc, crc32(&[object, b"}"])))
+}
+
+/// Split the sequence number and CRC field from the end of a JSON object, returning the object
+/// without the field and its closing brace, the sequence number and the CRC in the field.
+fn split(body: &[u8]) -> Option<(&[u8], u16, u32)> {
+    let body = body.trim_ascii_end();
+
+    if body.len() < FIELD_LENGTH + 1 || body.last() != Some(&b'}') {
+        return None;
+    }
+
+    let offset = body.len() - 1 - FIELD_LENGTH;
+    let f = &body[offset + 1..body.len() - 1];
+
+    if !f.starts_with(br#""crc":""#) || f[11] != b':' || f[20] != b'"' {
+        return None;
+    }
+
+    // The object without the field.
+    let object = match body[offset] {
+        b',' => &body[..offset],
+        b'{' => &body[..=offset],
+        _ => return None,
+    };
+
+    let seq = hex(&f[7..11])?;
+    let crc = hex(&f[12..20])?;
+
+    Some((object, seq as u16, crc))
+}
+
+/// Check the sequence number and CRC of a response to the request with sequence number `seq`.
+/// Responses without a CRC are accepted, e.g. from firmware that does not support it.
+pub(crate) fn check(body: &[u8], seq: u16) -> Result<(), NoteError> {
+    verify(parse(body), seq)
+}
+
+/// Bytes kept at the end of a streamed response: the field, the closing brace and the newline.
+const TAIL: usize = FIELD_LENGTH + 10;
+
+/// Checks the sequence number and CRC of a response that is streamed, see [`check`]. Only the
+/// tail of the response that may hold the field is kept.
+pub(crate) struct Stream {
+    crc: Crc32,
+    tail: Vec<u8, TAIL>,
+}
+
+impl Stream {
+    pub(crate) fn new() -> Stream {
+        Stream {
+            crc: Crc32::new(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Add the next bytes of the response.
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        // Bytes pushed out of the tail are part of the object.
+        let out = (self.tail.len() + bytes.len()).saturating_sub(TAIL);
+        let from_tail = out.min(self.tail.len());
+
+        self.crc.update(&self.tail[..from_tail]);
+        self.crc.update(&bytes[..out - from_tail]);
+
+        let kept = self.tail.len() - from_tail;
+        self.tail.rotate_left(from_tail);
+        self.tail.truncate(kept);
+        self.tail.extend_from_slice(&bytes[out - from_tail..]).ok();
+    }
+
+    /// Check the complete response against the request with sequence number `seq`.
+    pub(crate) fn check(mut self, seq: u16) -> Result<(), NoteError> {
+        let parsed = split(&self.tail).map(|(object, s, crc)| {
+            self.crc.update(object);
+            self.crc.update(b"}");
+            (s, crc, self.crc.finish())
+        });
+
+        verify(parsed, seq)
+    }
+}
+
+fn verify(parsed: Option<(u16, u32, u32)>, seq: u16) -> Result<(), NoteError> {
+    match parsed {
+        Some((s, crc, actual)) if s != seq || crc != actual => {
+            error!(
+                "crc: mismatch, expected sequence {} (got {}), crc {:x} (got {:x}).",
+                seq, s, actual, crc
+            );
+            Err(NoteError::CrcError)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::fixture::{notecard, recovers, status};
+    use crate::emulator::{Emulator, Fault};
+    use crate::{NoteState, Notecard};
+
+    #[test]
+    fn crc32_check() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF43926);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF43926);
+    }
+
+    #[test]
+    fn check_response() {
+        let crc = crc32(&[br#"{"status":"{normal}"}"#]);
+        let mut body = std::vec::Vec::from(&br#"{"status":"{normal}","#[..]);
+        body.extend_from_slice(&field(2, crc));
+        body.extend_from_slice(b"}\r\n");
+
+        check(&body, 2).unwrap();
+        assert!(matches!(check(&body, 3), Err(NoteError::CrcError)));
+
+        // corrupted
+        body[3] = b'X';
+        assert!(matches!(check(&body, 2), Err(NoteError::CrcError)));
+    }
+
+    #[test]
+    fn check_without_crc() {
+        check(br#"{"status":"{normal}"}"#, 2).unwrap();
+        check(b"{}\r\n", 2).unwrap();
+        check(b"", 2).unwrap();
+    }
+
+    fn stream(body: &[u8], split: usize, seq: u16) -> Result<(), NoteError> {
+        let mut s = Stream::new();
+        body.chunks(split).for_each(|c| s.update(c));
+        s.check(seq)
+    }
+
+    #[test]
+    fn check_streamed_response() {
+        let object = br#"{"payload":"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"}"#;
+        let mut body = std::vec::Vec::from(&object[..object.len() - 1]);
+        body.push(b',');
+        body.extend_from_slice(&field(7, crc32(&[object])));
+        body.extend_from_slice(b"}\r\n");
+
+        for split in [1, 2, 7, 31, 32, 33, 200] {
+            stream(&body, split, 7).unwrap();
+            assert!(matches!(stream(&body, split, 8), Err(NoteError::CrcError)));
+            stream(object, split, 7).unwrap();
+        }
+
+        let mut empty = std::vec::Vec::from(&b"{"[..]);
+        empty.extend_from_slice(&field(7, crc32(&[b"{}"])));
+        empty.extend_from_slice(b"}\r\n");
+        stream(&empty, 3, 7).unwrap();
+
+        // corrupted
+        body[3] = b'X';
+        assert!(matches!(stream(&body, 5, 7), Err(NoteError::CrcError)));
+    }
+
+    #[test]
+    fn request_crc() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.crc = true);
+
+        status(&mut note).unwrap();
+        status(&mut note).unwrap();
+        assert_eq!(note.proto.seq, 2);
+        assert!(emu.requests()[1].get("crc").is_none());
+
+        // A response to an earlier request.
+        emu.inject(Fault::Garbage(
+            b"{\"status\":\"{normal}\",\"crc\":\"0001:00000000\"}\r\n".to_vec(),
+        ));
+        assert!(matches!(status(&mut note), Err(NoteError::CrcError)));
+        assert!(matches!(note.proto.state, NoteState::Request));
+        recovers(&mut note, &emu);
+    }
+}
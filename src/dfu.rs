@@ -2,47 +2,44 @@
 
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{Deserialize, Serialize};
 
-use super::{FutureResponse, NoteError, Notecard};
+use super::{Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport::Transport;
 
-pub struct DFU<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+pub struct DFU<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> DFU<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> DFU<'_, IOM, BS> {
+impl<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> DFU<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> DFU<'_, IOM, BS, C, A> {
         DFU { note }
     }
 
     /// Retrieves downloaded firmware data from the Notecard.
     /// Note: this request is functional only when the Notecard has been set to
     /// dfu mode with a `hub.set`, `mode:dfu` request.
+    ///
+    /// Payloads larger than the buffer can be decoded while they are read with
+    /// [`FutureResponse::wait_payload`], using `PS = 0`.
     pub fn get<const PS: usize>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         length: usize,
         offset: Option<usize>,
-    ) -> Result<FutureResponse<'a, res::Get<PS>, IOM, BS>, NoteError> {
-        self.note.request(
-            delay,
-            req::Get {
-                req: "dfu.get",
-                length,
-                offset,
-            },
-        )?;
+    ) -> Result<FutureResponse<'a, res::Get<PS>, IOM, BS, C, A, req::Get<PS>>, NoteError> {
+        let request = self
+            .note
+            .request(delay, req::Get::<PS> { length, offset })?;
 
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Gets and sets the background download status of MCU host or Notecard
     /// firmware updates.
     pub fn status(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         name: Option<req::StatusName>,
         stop: Option<bool>,
         status: Option<&str>,
@@ -50,7 +47,7 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> D
         vvalue: Option<&str>, // This is not JSON :(
         on: Option<bool>,
         err: Option<&str>,
-    ) -> Result<FutureResponse<'a, res::Status, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Status::new(name, stop, status, version, vvalue, on, err),
@@ -63,16 +60,20 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> D
 pub mod req {
     use super::*;
 
+    /// Request for a payload of at most `PS` bytes, see [`res::Get`].
     #[derive(Serialize, Deserialize, Debug, defmt::Format, Default)]
-    pub struct Get {
-        pub req: &'static str,
-
+    pub struct Get<const PS: usize> {
         pub length: usize,
 
         #[serde(skip_serializing_if = "Option::is_none")]
         pub offset: Option<usize>,
     }
 
+    impl<const PS: usize> NotecardRequest for Get<PS> {
+        const NAME: &'static str = "dfu.get";
+        type Response = res::Get<PS>;
+    }
+
     #[derive(Serialize, Deserialize, Debug, defmt::Format, PartialEq)]
     #[serde(rename_all = "lowercase")]
     pub enum StatusName {
@@ -104,8 +105,6 @@ pub mod req {
 
     #[derive(Serialize, Deserialize, Debug, defmt::Format, Default)]
     pub struct Status<'a> {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub name: Option<StatusName>,
 
@@ -131,6 +130,11 @@ pub mod req {
         pub err: Option<&'a str>,
     }
 
+    impl NotecardRequest for Status<'_> {
+        const NAME: &'static str = "dfu.status";
+        type Response = res::Status;
+    }
+
     impl Status<'_> {
         pub fn new<'a>(
             name: Option<req::StatusName>,
@@ -146,7 +150,6 @@ pub mod req {
             // When on is `false` we set `on` to `None` and `off` to `Some(True)`.
             // This way we are not sending the `on` and `off` parameters together.
             Status {
-                req: "dfu.status",
                 name,
                 stop,
                 status,
@@ -228,7 +231,7 @@ mod tests {
     fn test_status_req() {
         // Test basic request
         let req = req::Status::new(None, None, None, None, None, None, None);
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"dfu.status"}"#);
 
         // Test a bunch of fields set
@@ -255,7 +258,7 @@ mod tests {
             Some(true),
             Some("test error"),
         );
-        let res: heapless::String<512> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<512> = crate::ser::request_string(&req).unwrap();
         assert_eq!(
             res,
             r#"{"req":"dfu.status","name":"user","stop":true,"status":"test status","version":"{\"org\":\"Organization\",\"product\":\"Product\",\"description\":\"Firmware Description\",\"firmware\":\"Firmware Name\",\"version\":\"Firmware Version 1.0.0\",\"ver_major\":1,\"ver_minor\":0,\"ver_patch\":0,\"ver_build\":12345,\"built\":\"Some Sunny Day In December\",\"builder\":\"The Compnay\"}","vvalue":"usb:1;high:1;normal:1;low:0;dead:0","on":true,"err":"test error"}"#
@@ -263,7 +266,7 @@ mod tests {
 
         // Test off set
         let req = req::Status::new(None, None, None, None, None, Some(false), None);
-        let res: heapless::String<256> = serde_json_core::to_string(&req).unwrap();
+        let res: heapless::String<256> = crate::ser::request_string(&req).unwrap();
         assert_eq!(res, r#"{"req":"dfu.status","off":true}"#);
     }
 
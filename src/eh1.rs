@@ -0,0 +1,177 @@
+//! Support for [embedded-hal 1.0](https://docs.rs/embedded-hal/1.0.0/embedded_hal/), enabled by
+//! the `eh1` feature.
+//!
+//! A Notecard on an `embedded-hal` 1.0 I2C bus takes `embedded-hal` 1.0 delays directly:
+//!
+//! ```ignore
+//! use blues_notecard::Notecard;
+//!
+//! let mut note: Notecard<_> = Notecard::new_eh1(i2c);
+//!
+//! note.initialize(&mut delay)?;
+//! let status = note.card().status(&mut delay)?.wait(&mut delay)?;
+//! ```
+
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital::InputPin;
+use embedded_hal_1::i2c::{self, Error as _};
+
+use crate::transport::i2c::{self as proto, block_on, I2cBus};
+use crate::transport::Transport;
+use crate::{Eh1, NoteError, Notecard, NotecardConfig};
+
+/// The Notecard connected over an `embedded-hal` 1.0 I2C bus.
+pub struct I2c<IOM: i2c::I2c> {
+    i2c: IOM,
+    addr: u8,
+}
+
+impl<IOM: i2c::I2c> I2c<IOM> {
+    pub fn new(i2c: IOM, addr: u8) -> I2c<IOM> {
+        I2c { i2c, addr }
+    }
+
+    /// Free the I2C device.
+    pub fn free(self) -> IOM {
+        self.i2c
+    }
+
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+}
+
+impl<IOM: i2c::I2c> I2cBus for I2c<IOM> {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self.i2c
+            .write(self.addr, bytes)
+            .map_err(|e| NoteError::I2cWriteError(e.kind().into()))
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), NoteError> {
+        self.i2c
+            .read(self.addr, buf)
+            .map_err(|e| NoteError::I2cReadError(e.kind().into()))
+    }
+}
+
+impl<IOM: i2c::I2c> Transport for I2c<IOM> {
+    type Hal = Eh1;
+
+    fn ping(&mut self) -> bool {
+        block_on(I2cBus::write(self, &[])).is_ok()
+    }
+
+    fn data_query(&mut self) -> Result<usize, NoteError> {
+        block_on(proto::data_query(self))
+    }
+
+    fn read(&mut self, avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError> {
+        block_on(proto::read(self, avail, buf))
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        block_on(proto::write(self, chunk))
+    }
+}
+
+impl<IOM: i2c::I2c, const BUF_SIZE: usize> Notecard<I2c<IOM>, BUF_SIZE> {
+    /// Create a driver for a Notecard on an `embedded-hal` 1.0 I2C bus, see
+    /// [`Notecard::new`](crate::Notecard::new).
+    pub fn new_eh1(i2c: IOM) -> Notecard<I2c<IOM>, BUF_SIZE> {
+        Self::new_eh1_with_config(i2c, NotecardConfig::default())
+    }
+
+    pub fn new_eh1_with_config(i2c: IOM, c: NotecardConfig) -> Notecard<I2c<IOM>, BUF_SIZE> {
+        let i2c = I2c::new(i2c, c.i2c_addr);
+        Self::new_with_transport(i2c, c)
+    }
+}
+
+impl<D: DelayNs> crate::Delay<Eh1> for D {
+    fn delay_ms(&mut self, ms: u16) {
+        DelayNs::delay_ms(self, ms.into())
+    }
+}
+
+/// An `embedded-hal` 1.0 input pin connected to ATTN, see [`AttnPin`](crate::AttnPin).
+pub struct AttnPin<P: InputPin>(pub P);
+
+impl<P: InputPin> crate::Attn for AttnPin<P> {
+    fn wait<HAL>(&mut self, delay: &mut impl crate::Delay<HAL>, timeout: u32) -> u32 {
+        // A pin that can not be read is taken as asserted, so that the Notecard is polled.
+        crate::attn_poll(|| self.0.is_high().unwrap_or(true), delay, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn request_response() {
+        let mut req = b"{\"req\":\"card.status\"}\n".to_vec();
+        req.insert(0, 22);
+
+        let res = b"{\"status\":\"{normal}\",\"storage\":8}\r\n";
+        let mut read = vec![0, res.len() as u8];
+        read.extend_from_slice(res);
+
+        let exp = [
+            // handshake
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![0, 0]),
+            // request
+            Transaction::write(0x17, req),
+            // response
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![res.len() as u8, 0]),
+            Transaction::write(0x17, vec![0, res.len() as u8]),
+            Transaction::read(0x17, read),
+        ];
+
+        let i2c = Mock::new(&exp);
+        let mut c: Notecard<I2c<Mock>> = Notecard::new_eh1(i2c);
+        let mut delay = NoopDelay::new();
+
+        let status = c
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(status.status, "{normal}");
+
+        c.suspend().0.free().done();
+    }
+
+    #[test]
+    fn bus_error_kind() {
+        use crate::error::{BusError, NoAcknowledgeSource};
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource as Source};
+
+        let exp = [
+            Transaction::write(0x17, vec![0, 0])
+                .with_error(ErrorKind::NoAcknowledge(Source::Address)),
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![0, 0]).with_error(ErrorKind::ArbitrationLoss),
+        ];
+
+        let mut i2c = I2c::new(Mock::new(&exp), 0x17);
+
+        let e = i2c.data_query().unwrap_err();
+        assert!(matches!(e, NoteError::I2cWriteError(_)));
+        assert_eq!(
+            e.bus_error(),
+            Some(BusError::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+
+        let e = i2c.data_query().unwrap_err();
+        assert!(matches!(e, NoteError::I2cReadError(_)));
+        assert_eq!(e.bus_error(), Some(BusError::ArbitrationLoss));
+
+        i2c.free().done();
+    }
+}
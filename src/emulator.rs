@@ -0,0 +1,713 @@
+//! An emulated Notecard for testing the driver without hardware, enabled by the `emulator` feature.
+//!
+//! The [`Emulator`] implements the `embedded-hal` I2C traits, and the `embedded-hal-async` ones with
+//! the `async` feature, and speaks the
+//! [serial-over-I2C protocol](https://dev.blues.io/notecard/notecard-guides/serial-over-i2c-protocol/).
+//! Requests are answered by a small in-memory model of notefiles and the Notehub configuration,
+//! and any request can be scripted with [`Emulator::respond`] or [`Emulator::on`]. Bus and protocol
+//! errors can be injected with [`Emulator::inject`].
+//!
+//! ```
+//! use blues_notecard::{emulator::Emulator, Notecard, StdDelay};
+//!
+//! let emu = Emulator::new();
+//! emu.respond("card.time", r#"{"time":1599769214,"zone":"UTC,Unknown"}"#);
+//!
+//! let mut note: Notecard<_> = Notecard::new(emu.clone());
+//! let mut delay = StdDelay;
+//! note.initialize(&mut delay).unwrap();
+//!
+//! let time = note.card().time(&mut delay).unwrap().wait(&mut delay).unwrap();
+//! assert_eq!(time.time, Some(1599769214));
+//! assert_eq!(emu.requests()[0]["req"], "card.time");
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::rc::Rc;
+
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+use serde_json::{json, Map, Value};
+
+use crate::crc;
+
+/// Handler answering a request, see [`Emulator::on`].
+pub type Handler = Box<dyn FnMut(&Value) -> Value>;
+
+/// I2C errors returned by the emulator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Nothing is answering on the address.
+    Nak,
+
+    /// The length prefix of a chunk does not match the chunk.
+    InvalidChunk,
+}
+
+/// Faults that can be injected into the emulator, each fault applies once.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// NAK the next `n` I2C transfers, they are not received by the Notecard.
+    Nak(usize),
+
+    /// Send at most `n` bytes in the next chunk of a response, fewer than the host asked for.
+    TruncatedChunk(usize),
+
+    /// Send bytes of the waiting response while the host is only querying for available bytes.
+    /// The sent bytes are lost.
+    SentOnQuery,
+
+    /// Leave a stale response waiting for the host, as if from a request before the host reset.
+    StaleResponse(Vec<u8>),
+
+    /// Hold back the next response for `n` data queries.
+    Slow(usize),
+
+    /// Replace the next response with garbage.
+    Garbage(Vec<u8>),
+
+    /// Repeat the waiting response over and over, so that it never ends. Unlike the other faults
+    /// this one stays.
+    Endless,
+}
+
+/// An emulated Notecard. Cloning the emulator gives a new handle to the same Notecard, so that one
+/// handle can be given to the driver while the test inspects and scripts the other.
+#[derive(Clone)]
+pub struct Emulator {
+    state: Rc<RefCell<State>>,
+}
+
+struct State {
+    addr: u8,
+
+    /// Request being received.
+    request: Vec<u8>,
+
+    /// Response waiting to be read.
+    response: VecDeque<u8>,
+
+    /// Number of bytes asked for by the last read request.
+    read_len: usize,
+
+    /// Injected faults that have not yet been applied.
+    faults: Vec<Fault>,
+
+    /// Number of data queries the response is held back for.
+    hold: usize,
+
+    /// All received requests.
+    requests: Vec<Value>,
+
+    /// Scripted handlers, these take precedence over the model.
+    handlers: HashMap<String, Handler>,
+
+    model: Model,
+}
+
+/// The in-memory state of the Notecard.
+#[derive(Default)]
+struct Model {
+    /// Notes by notefile, keyed by note ID.
+    notefiles: BTreeMap<String, BTreeMap<String, Value>>,
+
+    /// Configuration set with `hub.set`.
+    hub: Map<String, Value>,
+
+    /// Last assigned note ID.
+    id: u32,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
+
+impl Emulator {
+    /// Create an emulated Notecard on the default I2C address (`0x17`).
+    pub fn new() -> Emulator {
+        Emulator::new_with_addr(0x17)
+    }
+
+    pub fn new_with_addr(addr: u8) -> Emulator {
+        Emulator {
+            state: Rc::new(RefCell::new(State {
+                addr,
+                request: Vec::new(),
+                response: VecDeque::new(),
+                read_len: 0,
+                faults: Vec::new(),
+                hold: 0,
+                requests: Vec::new(),
+                handlers: HashMap::new(),
+                model: Model::default(),
+            })),
+        }
+    }
+
+    /// Always answer `req` with `response`, which must be a JSON object.
+    pub fn respond(&self, req: &str, response: &str) {
+        let response: Value = serde_json::from_str(response).expect("invalid response JSON");
+        self.on(req, move |_| response.clone());
+    }
+
+    /// Answer `req` with the return value of `handler`, which is given the full request.
+    pub fn on(&self, req: &str, handler: impl FnMut(&Value) -> Value + 'static) {
+        self.state
+            .borrow_mut()
+            .handlers
+            .insert(req.into(), Box::new(handler));
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.borrow().requests.clone()
+    }
+
+    /// The notes in a notefile, keyed by note ID.
+    pub fn notes(&self, file: &str) -> BTreeMap<String, Value> {
+        self.state
+            .borrow()
+            .model
+            .notefiles
+            .get(file)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Queue data to be read by the host as if the Notecard had sent it, e.g. a response left
+    /// over from before the host was reset.
+    pub fn push_response(&self, data: &[u8]) {
+        self.state.borrow_mut().response.extend(data);
+    }
+
+    /// Bytes waiting to be read by the host.
+    pub fn pending(&self) -> usize {
+        self.state.borrow().response.len()
+    }
+
+    /// Inject a fault, it is applied the next time it is possible.
+    pub fn inject(&self, fault: Fault) {
+        let mut state = self.state.borrow_mut();
+
+        match fault {
+            Fault::StaleResponse(response) => state.response.extend(response),
+            fault => state.faults.push(fault),
+        }
+    }
+}
+
+impl State {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match bytes {
+            // ping
+            [] => Ok(()),
+
+            // ask for reading `n` bytes.
+            [0, n] => {
+                self.read_len = *n as usize;
+                Ok(())
+            }
+
+            [len, chunk @ ..] if *len as usize == chunk.len() => {
+                for b in chunk {
+                    self.request.push(*b);
+
+                    if *b == b'\n' {
+                        let request = core::mem::take(&mut self.request);
+                        self.process(&request);
+                    }
+                }
+                Ok(())
+            }
+
+            _ => Err(Error::InvalidChunk),
+        }
+    }
+
+    /// Take the first injected fault matching `f`.
+    fn fault(&mut self, f: impl Fn(&Fault) -> bool) -> Option<Fault> {
+        let i = self.faults.iter().position(f)?;
+        Some(self.faults.remove(i))
+    }
+
+    /// Whether the next transfer should be NAK'ed.
+    fn nak(&mut self) -> bool {
+        match self.fault(|f| matches!(f, Fault::Nak(_))) {
+            Some(Fault::Nak(n)) if n > 0 => {
+                if n > 1 {
+                    self.faults.push(Fault::Nak(n - 1));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        buf.fill(0);
+
+        let held = self.hold > 0;
+        if held && self.read_len == 0 {
+            self.hold -= 1;
+        }
+
+        let available = if held { 0 } else { self.response.len() };
+
+        let mut sent = self
+            .read_len
+            .min(available)
+            .min(buf.len().saturating_sub(2));
+
+        if self.read_len == 0
+            && available > 0
+            && self.fault(|f| matches!(f, Fault::SentOnQuery)).is_some()
+        {
+            // The bytes do not fit in the buffer of the host, and are lost.
+            let lost = available.min(4);
+            self.response.drain(..lost);
+
+            buf[0] = self.response.len().min(255) as u8;
+            buf[1] = lost as u8;
+            return Ok(());
+        }
+
+        if sent > 0
+            && let Some(Fault::TruncatedChunk(n)) =
+                self.fault(|f| matches!(f, Fault::TruncatedChunk(_)))
+        {
+            sent = sent.min(n);
+        }
+
+        self.read_len = 0;
+        let endless = self.faults.contains(&Fault::Endless);
+
+        for b in buf.iter_mut().skip(2).take(sent) {
+            *b = self.response.pop_front().unwrap();
+
+            if endless {
+                self.response.push_back(*b);
+            }
+        }
+
+        if let Some(header) = buf.get_mut(..2) {
+            header[0] = if held {
+                0
+            } else {
+                self.response.len().min(255) as u8
+            };
+            header[1] = sent as u8;
+        }
+
+        Ok(())
+    }
+
+    /// Process a full request line and queue the response.
+    fn process(&mut self, line: &[u8]) {
+        let mut request: Value = match serde_json::from_slice(line) {
+            Ok(r) => r,
+            Err(e) => {
+                self.queue(&json!({ "err": format!("invalid JSON: {e} {{io}}") }), None);
+                return;
+            }
+        };
+
+        // Requests with a sequence number and CRC are answered with the same sequence number.
+        let seq = match crc::parse(line) {
+            Some((seq, crc, actual)) if crc == actual => {
+                request.as_object_mut().unwrap().remove("crc");
+                Some(seq)
+            }
+            Some(_) => {
+                self.queue(&json!({ "err": "CRC error {io}" }), None);
+                return;
+            }
+            None => None,
+        };
+
+        self.requests.push(request.clone());
+
+        // Commands are not answered.
+        let (name, answer) = match (&request["req"], &request["cmd"]) {
+            (Value::String(req), _) => (req.clone(), true),
+            (_, Value::String(cmd)) => (cmd.clone(), false),
+            _ => {
+                self.queue(
+                    &json!({ "err": "no request or command specified {io}" }),
+                    seq,
+                );
+                return;
+            }
+        };
+
+        let response = match self.handlers.get_mut(&name) {
+            Some(handler) => handler(&request),
+            None => self.model.request(&name, &request),
+        };
+
+        if answer {
+            self.queue(&response, seq);
+        }
+    }
+
+    fn queue(&mut self, response: &Value, seq: Option<u16>) {
+        if let Some(Fault::Slow(n)) = self.fault(|f| matches!(f, Fault::Slow(_))) {
+            self.hold = n;
+        }
+
+        if let Some(Fault::Garbage(garbage)) = self.fault(|f| matches!(f, Fault::Garbage(_))) {
+            self.response.extend(garbage);
+            return;
+        }
+
+        let response = serde_json::to_vec(response).unwrap();
+
+        match seq {
+            Some(seq) => {
+                let crc = crc::crc32(&[&response]);
+                let object = &response[..response.len() - 1];
+
+                self.response.extend(object);
+                if object.len() > 1 {
+                    self.response.push_back(b',');
+                }
+                self.response.extend(crc::field(seq, crc));
+                self.response.push_back(b'}');
+            }
+            None => self.response.extend(response),
+        }
+
+        self.response.extend(b"\r\n");
+    }
+}
+
+impl Model {
+    fn request(&mut self, name: &str, request: &Value) -> Value {
+        let file = request["file"].as_str();
+        let note = request["note"].as_str();
+
+        match name {
+            "card.status" => json!({
+                "status": "{normal}",
+                "usb": true,
+                "storage": 8,
+                "time": 1599684765,
+                "connected": true,
+            }),
+
+            "card.version" => json!({
+                "version": "notecard-7.5.2.17004",
+                "device": "dev:000000000000000",
+                "name": "Blues Wireless Notecard",
+                "sku": "NOTE-NBGLN",
+                "board": "5.13",
+                "api": 7,
+                "body": {
+                    "org": "Blues Wireless",
+                    "product": "Notecard",
+                    "version": "notecard-u5-7.5.2",
+                    "ver_major": 7,
+                    "ver_minor": 5,
+                    "ver_patch": 2,
+                    "ver_build": 17004,
+                    "built": "Nov 26 2024 14:01:26",
+                },
+            }),
+
+            "card.time" => json!({ "err": "time is not yet set", "zone": "UTC,Unknown" }),
+
+            "hub.set" => {
+                for (k, v) in request.as_object().unwrap() {
+                    if k != "req" {
+                        self.hub.insert(k.clone(), v.clone());
+                    }
+                }
+                json!({})
+            }
+
+            "hub.get" => {
+                let mut hub = self.hub.clone();
+                hub.insert("device".into(), "dev:000000000000000".into());
+                Value::Object(hub)
+            }
+
+            "hub.sync" | "hub.log" | "card.attn" => json!({}),
+
+            "hub.sync.status" => json!({ "status": "completed {sync-end}", "completed": 10 }),
+
+            "note.add" => {
+                let file = file.unwrap_or("data.qo").to_string();
+                let id = match note {
+                    Some(note) => note.to_string(),
+                    None => {
+                        self.id += 1;
+                        self.id.to_string()
+                    }
+                };
+
+                let notes = self.notefiles.entry(file).or_default();
+                notes.insert(id, note_value(request));
+                json!({ "total": notes.len() })
+            }
+
+            "note.update" => match (file, note) {
+                (Some(file), Some(note)) => {
+                    let notes = self.notefiles.entry(file.into()).or_default();
+                    notes.insert(note.into(), note_value(request));
+                    json!({})
+                }
+                _ => json!({ "err": "no notefile or note specified {io}" }),
+            },
+
+            "note.get" | "note.delete" => {
+                let found = match (file, note) {
+                    (Some(file), Some(note)) => self.notefiles.get_mut(file).and_then(|notes| {
+                        if name == "note.delete" || request["delete"] == true {
+                            notes.remove(note)
+                        } else {
+                            notes.get(note).cloned()
+                        }
+                    }),
+                    _ => None,
+                };
+
+                match found {
+                    Some(_) if name == "note.delete" => json!({}),
+                    Some(mut found) => {
+                        found["note"] = note.unwrap().into();
+                        found
+                    }
+                    None => json!({ "err": "note not found {note-noexist}" }),
+                }
+            }
+
+            "note.template" => json!({ "bytes": 40 }),
+
+            _ => json!({ "err": format!("unknown request: {name} {{not-supported}}") }),
+        }
+    }
+}
+
+/// The stored part of a note.
+fn note_value(request: &Value) -> Value {
+    let mut note = Map::new();
+    for k in ["body", "payload"] {
+        if let Some(v) = request.get(k) {
+            note.insert(k.into(), v.clone());
+        }
+    }
+    Value::Object(note)
+}
+
+impl Write<SevenBitAddress> for Emulator {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+
+        if addr != state.addr || state.nak() {
+            return Err(Error::Nak);
+        }
+
+        state.write(bytes)
+    }
+}
+
+impl Read<SevenBitAddress> for Emulator {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+
+        if addr != state.addr || state.nak() {
+            return Err(Error::Nak);
+        }
+
+        state.read(buf)
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::i2c::Error for Error {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        match self {
+            Error::Nak => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Error::InvalidChunk => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::i2c::ErrorType for Emulator {
+    type Error = Error;
+}
+
+/// The emulator answers at once, transfers complete when they are first polled.
+#[cfg(feature = "async")]
+impl embedded_hal_async::i2c::I2c for Emulator {
+    async fn transaction(
+        &mut self,
+        addr: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error> {
+        use embedded_hal_1::i2c::Operation;
+
+        for op in operations {
+            match op {
+                Operation::Read(buf) => Read::read(self, addr, buf)?,
+                Operation::Write(bytes) => Write::write(self, addr, bytes)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Helpers for testing the driver against the emulator.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::Emulator;
+    use crate::transport::I2c;
+    use crate::{NoteError, NoteState, Notecard, NotecardConfig};
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+
+    /// An initialized driver for `emu`, with the default configuration changed by `config`.
+    pub(crate) fn notecard<const BS: usize>(
+        emu: &Emulator,
+        config: impl FnOnce(&mut NotecardConfig),
+    ) -> Notecard<I2c<Emulator>, BS> {
+        let mut c = NotecardConfig::default();
+        config(&mut c);
+
+        let mut note = Notecard::new_with_config(emu.clone(), c);
+        note.initialize(&mut NoopDelay::new()).unwrap();
+        note
+    }
+
+    /// Request `card.status`.
+    pub(crate) fn status<const BS: usize>(
+        note: &mut Notecard<I2c<Emulator>, BS>,
+    ) -> Result<(), NoteError> {
+        let mut delay = NoopDelay::new();
+        note.card().status(&mut delay)?.wait(&mut delay)?;
+        Ok(())
+    }
+
+    /// Check that the driver recovers with the next request.
+    pub(crate) fn recovers<const BS: usize>(
+        note: &mut Notecard<I2c<Emulator>, BS>,
+        emu: &Emulator,
+    ) {
+        status(note).unwrap();
+        assert!(matches!(note.proto.state, NoteState::Request));
+        assert_eq!(emu.pending(), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::{notecard, recovers, status};
+    use super::*;
+    use crate::{NoteError, NoteState, Notecard};
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+
+    #[test]
+    fn ping() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        assert!(note.ping());
+
+        let mut note: Notecard<_> = Notecard::new(Emulator::new_with_addr(0x18));
+        assert!(!note.ping());
+    }
+
+    #[test]
+    fn fault_nak() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        emu.inject(Fault::Nak(1));
+        assert!(matches!(
+            status(&mut note),
+            Err(NoteError::I2cWriteError(_))
+        ));
+        recovers(&mut note, &emu);
+
+        let f = note.card().status(&mut delay).unwrap();
+        emu.inject(Fault::Nak(1));
+        assert!(matches!(
+            f.wait(&mut delay),
+            Err(NoteError::I2cWriteError(_))
+        ));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn fault_truncated_chunk() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+
+        emu.inject(Fault::TruncatedChunk(3));
+        status(&mut note).unwrap();
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn fault_sent_on_query() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+
+        emu.inject(Fault::SentOnQuery);
+        assert!(matches!(status(&mut note), Err(NoteError::RemainingData)));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn fault_stale_response() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+
+        // The request seems to fail, but the Notecard answers it anyway.
+        emu.inject(Fault::Nak(1));
+        assert!(status(&mut note).is_err());
+        emu.inject(Fault::StaleResponse(b"{\"total\":1}\r\n".to_vec()));
+        recovers(&mut note, &emu);
+
+        // The host resets while a response is waiting.
+        emu.inject(Fault::StaleResponse(b"{\"total\":1}\r\n".to_vec()));
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn fault_slow() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+
+        emu.inject(Fault::Slow(2));
+        status(&mut note).unwrap();
+
+        // Times out after five polls, at 0, 25, .., 100 ms. The response is available at the next.
+        emu.inject(Fault::Slow(5));
+        assert!(matches!(status(&mut note), Err(NoteError::TimeOut)));
+        assert!(!matches!(note.proto.state, NoteState::Request));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn fault_garbage() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+
+        emu.inject(Fault::Garbage(b"\x00\xffnot json\r\n".to_vec()));
+        let e = status(&mut note).unwrap_err();
+        assert!(matches!(e, NoteError::DeserError(_)));
+        assert_eq!(e.request(), Some("card.status"));
+        assert!(matches!(note.proto.state, NoteState::Request));
+        recovers(&mut note, &emu);
+    }
+}
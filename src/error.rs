@@ -0,0 +1,530 @@
+//! Tags of Notecard errors, deserialization errors, and the kinds of bus errors.
+//!
+//! The Notecard classifies errors by tags in the error string, e.g.
+//! `"note-noexist: note not found {note-noexist}"`. The tags of a
+//! [`NotecardError`](crate::NotecardError) are parsed into [`ErrorTags`], so that errors can be
+//! handled by their class instead of by their message:
+//!
+//! ```ignore
+//! match note.card().status(&mut delay)?.wait(&mut delay) {
+//!     Err(e) if e.tags().contains(ErrorTag::Busy) => retry_later(),
+//!     r => r?,
+//! }
+//! ```
+
+use core::fmt;
+use core::ops::Range;
+
+use heapless::String;
+
+/// The name of the request that failed, e.g. `note.add`.
+pub(crate) type RequestName = String<32>;
+
+/// The name of a request, or an empty name if it is unknown or too long.
+pub(crate) fn new_request_name(name: Option<&str>) -> RequestName {
+    name.and_then(|name| RequestName::try_from(name).ok())
+        .unwrap_or_default()
+}
+
+/// A response that could not be deserialized.
+#[derive(Debug, defmt::Format, Clone)]
+pub struct DeserializeError {
+    pub(crate) request: RequestName,
+    response: String<256>,
+}
+
+impl DeserializeError {
+    pub(crate) fn new(response: &[u8]) -> DeserializeError {
+        let response = core::str::from_utf8(response).unwrap_or("[invalid utf-8]");
+        let mut s = String::new();
+        s.push_str(response).ok();
+
+        DeserializeError {
+            request: RequestName::new(),
+            response: s,
+        }
+    }
+
+    /// The name of the request, if known.
+    pub fn request(&self) -> Option<&str> {
+        Some(self.request.as_str()).filter(|r| !r.is_empty())
+    }
+
+    /// The response, or the part of it that fits.
+    pub fn response(&self) -> &str {
+        &self.response
+    }
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(request) = self.request() {
+            write!(f, "{request}: ")?;
+        }
+        write!(f, "failed to deserialize response: {}", self.response)
+    }
+}
+
+/// A tag of a Notecard error, such as `{io}`.
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorTag {
+    /// `{io}`: transient error on the bus or in the Notecard, the request may be retried.
+    Io,
+
+    /// `{not-supported}`: request is not supported by the Notecard or its firmware.
+    NotSupported,
+
+    /// `{network}`: the network is not available.
+    Network,
+
+    /// `{bad-bin}`: invalid binary data.
+    BadBin,
+
+    /// `{busy}`: the Notecard is busy.
+    Busy,
+
+    /// `{dfu-in-progress}`: the firmware of the Notecard is being updated.
+    DfuInProgress,
+
+    /// `{file-storage-full}`: the file system of the Notecard is full.
+    FileStorageFull,
+
+    /// `{note-noexist}`: the note does not exist.
+    NoteNoexist,
+
+    /// `{note-exists}`: the note already exists.
+    NoteExists,
+
+    /// `{file-noexist}`: the notefile does not exist.
+    FileNoexist,
+
+    /// Any other tag, see [`NotecardError::tag_names`](crate::NotecardError::tag_names).
+    Other,
+}
+
+impl ErrorTag {
+    const ALL: [ErrorTag; 11] = [
+        ErrorTag::Io,
+        ErrorTag::NotSupported,
+        ErrorTag::Network,
+        ErrorTag::BadBin,
+        ErrorTag::Busy,
+        ErrorTag::DfuInProgress,
+        ErrorTag::FileStorageFull,
+        ErrorTag::NoteNoexist,
+        ErrorTag::NoteExists,
+        ErrorTag::FileNoexist,
+        ErrorTag::Other,
+    ];
+
+    /// The tag by its name without braces, e.g. `io`.
+    pub fn from_name(name: &str) -> ErrorTag {
+        ErrorTag::ALL
+            .into_iter()
+            .find(|tag| tag.name() == name)
+            .unwrap_or(ErrorTag::Other)
+    }
+
+    /// The name of the tag without braces, empty for [`ErrorTag::Other`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorTag::Io => "io",
+            ErrorTag::NotSupported => "not-supported",
+            ErrorTag::Network => "network",
+            ErrorTag::BadBin => "bad-bin",
+            ErrorTag::Busy => "busy",
+            ErrorTag::DfuInProgress => "dfu-in-progress",
+            ErrorTag::FileStorageFull => "file-storage-full",
+            ErrorTag::NoteNoexist => "note-noexist",
+            ErrorTag::NoteExists => "note-exists",
+            ErrorTag::FileNoexist => "file-noexist",
+            ErrorTag::Other => "",
+        }
+    }
+}
+
+/// A set of [`ErrorTag`]s.
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorTags(u16);
+
+impl ErrorTags {
+    /// Parse the tags of an error string.
+    pub fn parse(err: &str) -> ErrorTags {
+        tag_names(err)
+            .map(ErrorTag::from_name)
+            .fold(ErrorTags::default(), ErrorTags::with)
+    }
+
+    /// The set with `tag` added.
+    pub fn with(self, tag: ErrorTag) -> ErrorTags {
+        ErrorTags(self.0 | 1 << tag as u8)
+    }
+
+    pub fn contains(&self, tag: ErrorTag) -> bool {
+        self.0 & 1 << tag as u8 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ErrorTag> + '_ {
+        ErrorTag::ALL.into_iter().filter(|tag| self.contains(*tag))
+    }
+}
+
+impl From<ErrorTag> for ErrorTags {
+    fn from(tag: ErrorTag) -> ErrorTags {
+        ErrorTags::default().with(tag)
+    }
+}
+
+/// The kind of an error on the bus to the Notecard, as reported by the driver of the bus. Drivers
+/// for `embedded-hal` 1.0 report the kind of their errors (see `embedded_hal::i2c::ErrorKind`),
+/// those for `embedded-hal` 0.2 do not, and their errors are [`BusError::Unknown`] unless they are
+/// classified with an [`ErrorKind`].
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    /// Bus error, e.g. a misplaced start or stop condition.
+    Bus,
+
+    /// Arbitration was lost to another controller.
+    ArbitrationLoss,
+
+    /// The Notecard did not acknowledge, e.g. because it is not ready. The address is not
+    /// acknowledged when the Notecard is not on the bus at all.
+    NoAcknowledge(NoAcknowledgeSource),
+
+    /// Data was not read fast enough.
+    Overrun,
+
+    /// The bus did not complete the transfer in time.
+    TimedOut,
+
+    /// The connection to the Notecard is gone, e.g. a stream that ended or a device that was
+    /// unplugged. Retrying does not help.
+    Disconnected,
+
+    /// An error the driver does not classify further.
+    Other,
+
+    /// The driver does not report the kind of its errors.
+    Unknown,
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BusError::Bus => "bus error",
+            BusError::ArbitrationLoss => "arbitration lost",
+            BusError::NoAcknowledge(NoAcknowledgeSource::Address) => "address not acknowledged",
+            BusError::NoAcknowledge(NoAcknowledgeSource::Data) => "data not acknowledged",
+            BusError::NoAcknowledge(NoAcknowledgeSource::Unknown) => "not acknowledged",
+            BusError::Overrun => "overrun",
+            BusError::TimedOut => "timed out",
+            BusError::Disconnected => "disconnected",
+            BusError::Other => "other error",
+            BusError::Unknown => "unknown error",
+        })
+    }
+}
+
+impl BusError {
+    /// Whether a transfer that failed with this error may succeed when it is tried again.
+    pub fn is_transient(&self) -> bool {
+        !matches!(self, BusError::Disconnected)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::ErrorKind> for BusError {
+    fn from(kind: std::io::ErrorKind) -> BusError {
+        use std::io::ErrorKind;
+
+        match kind {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted => {
+                BusError::TimedOut
+            }
+            ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::WriteZero
+            | ErrorKind::NotFound
+            | ErrorKind::PermissionDenied => BusError::Disconnected,
+            _ => BusError::Other,
+        }
+    }
+}
+
+/// Classifies the errors `E` of an `embedded-hal` 0.2 bus driver, which have no common kind.
+/// Implement it on a type of your own, and give that to the transport with
+/// [`I2c::with_error_kind`](crate::transport::I2c::with_error_kind) or
+/// [`Serial::with_error_kind`](crate::transport::Serial::with_error_kind):
+///
+/// ```ignore
+/// struct Kinds;
+///
+/// impl ErrorKind<hal::i2c::Error> for Kinds {
+///     fn kind(error: &hal::i2c::Error) -> BusError {
+///         match error {
+///             hal::i2c::Error::Nack => BusError::NoAcknowledge(NoAcknowledgeSource::Unknown),
+///             _ => BusError::Other,
+///         }
+///     }
+/// }
+///
+/// let i2c = I2c::new(i2c, 0x17).with_error_kind::<Kinds>();
+/// ```
+pub trait ErrorKind<E> {
+    fn kind(error: &E) -> BusError;
+}
+
+/// Does not classify errors, they are all [`BusError::Unknown`]. The default for the
+/// `embedded-hal` 0.2 transports.
+pub struct Unclassified;
+
+impl<E> ErrorKind<E> for Unclassified {
+    fn kind(_error: &E) -> BusError {
+        BusError::Unknown
+    }
+}
+
+/// What was not acknowledged, see [`BusError::NoAcknowledge`].
+#[derive(Debug, defmt::Format, Clone, Copy, PartialEq, Eq)]
+pub enum NoAcknowledgeSource {
+    Address,
+    Data,
+    Unknown,
+}
+
+#[cfg(any(feature = "eh1", feature = "async"))]
+impl From<embedded_hal_1::i2c::ErrorKind> for BusError {
+    fn from(kind: embedded_hal_1::i2c::ErrorKind) -> BusError {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource as Source};
+
+        match kind {
+            ErrorKind::Bus => BusError::Bus,
+            ErrorKind::ArbitrationLoss => BusError::ArbitrationLoss,
+            ErrorKind::NoAcknowledge(source) => BusError::NoAcknowledge(match source {
+                Source::Address => NoAcknowledgeSource::Address,
+                Source::Data => NoAcknowledgeSource::Data,
+                Source::Unknown => NoAcknowledgeSource::Unknown,
+            }),
+            ErrorKind::Overrun => BusError::Overrun,
+            _ => BusError::Other,
+        }
+    }
+}
+
+/// Byte ranges of the tags in an error string, including the braces. A tag is a name of
+/// alphanumeric characters, `-` and `_` in braces.
+fn tag_ranges(err: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut pos = 0;
+
+    core::iter::from_fn(move || {
+        while let Some(open) = err[pos..].find('{').map(|i| pos + i) {
+            let name = &err[open + 1..];
+            let len = name
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .unwrap_or(name.len());
+
+            pos = open + 1;
+
+            if len > 0 && name[len..].starts_with('}') {
+                pos = open + len + 2;
+                return Some(open..pos);
+            }
+        }
+
+        None
+    })
+}
+
+/// Whether a response is an error response, which has an `err` key in its top-level object. The
+/// key may be anywhere in the object, e.g. `{"zone":"UTC,Unknown","err":"time is not yet set"}`.
+pub(crate) fn is_error_response(body: &[u8]) -> bool {
+    top_level_key(body, b"err").is_some()
+}
+
+/// The string value of `key` in the top-level object of `body`, without unescaping it. Keys and
+/// values of nested objects are skipped.
+pub(crate) fn top_level_str<'a>(body: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    let rest = &body[top_level_key(body, key)?..];
+    let rest = rest.trim_ascii_start().strip_prefix(b":")?;
+    let rest = rest.trim_ascii_start().strip_prefix(b"\"")?;
+
+    let mut i = 0;
+    while i < rest.len() && rest[i] != b'"' {
+        i += if rest[i] == b'\\' { 2 } else { 1 };
+    }
+    (i < rest.len()).then(|| &rest[..i])
+}
+
+/// The position after `key` in the top-level object of `body`.
+fn top_level_key(body: &[u8], key: &[u8]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut object = false;
+    let mut expect_key = false;
+    let mut i = 0;
+
+    while i < body.len() {
+        match body[i] {
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while i < body.len() && body[i] != b'"' {
+                    i += if body[i] == b'\\' { 2 } else { 1 };
+                }
+
+                if expect_key && i < body.len() && &body[start..i] == key {
+                    return Some(i + 1);
+                }
+                expect_key = false;
+            }
+            b @ (b'{' | b'[') => {
+                if depth == 0 {
+                    object = b == b'{';
+                }
+                depth += 1;
+                expect_key = object && depth == 1;
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b',' => expect_key = object && depth == 1,
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+/// The names of the tags in an error string, without braces.
+pub(crate) fn tag_names(err: &str) -> impl Iterator<Item = &str> {
+    tag_ranges(err).map(|r| &err[r.start + 1..r.end - 1])
+}
+
+/// The error string without its tags and the whitespace around them.
+pub(crate) fn message(err: &str) -> String<256> {
+    let mut message = String::new();
+    let mut push = |part: &str| {
+        for word in part.split_whitespace() {
+            if !message.is_empty() {
+                message.push(' ').ok();
+            }
+            message.push_str(word).ok();
+        }
+    };
+
+    let mut pos = 0;
+    for r in tag_ranges(err) {
+        push(&err[pos..r.start]);
+        pos = r.end;
+    }
+    push(&err[pos..]);
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::fixture::{notecard, recovers};
+    use crate::emulator::Emulator;
+    use crate::{NoteError, Notecard};
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use serde_json::json;
+
+    #[test]
+    fn parse_tags() {
+        let err =
+            "error adding note: file storage full {file-storage-full}{io} {x:y} {} {gps-active}";
+
+        assert_eq!(
+            tag_names(err).collect::<std::vec::Vec<_>>(),
+            ["file-storage-full", "io", "gps-active"]
+        );
+
+        let tags = ErrorTags::parse(err);
+        assert_eq!(
+            tags.iter().collect::<std::vec::Vec<_>>(),
+            [ErrorTag::Io, ErrorTag::FileStorageFull, ErrorTag::Other]
+        );
+        assert!(!tags.contains(ErrorTag::Busy));
+
+        assert!(ErrorTags::parse("time is not yet set").is_empty());
+    }
+
+    #[test]
+    fn error_response() {
+        for body in [
+            &br#"{"err":"x {io}"}"#[..],
+            br#" {"zone":"UTC,Unknown","err":"time is not yet set"}"#,
+            br#"{"a":{"err":"x"},"b":["err"],"c":"\"err","err":"y"}"#,
+        ] {
+            assert!(is_error_response(body));
+        }
+
+        for body in [
+            &br#"{"status":"err"}"#[..],
+            br#"{"body":{"err":"x"}}"#,
+            br#"["err"]"#,
+            br#"{"a":"\"err\"","b":[1,"err"]}"#,
+            br#"{"errors":1}"#,
+        ] {
+            assert!(!is_error_response(body));
+        }
+    }
+
+    #[test]
+    fn top_level_string() {
+        let body = br#"{"body":{"req":"hub.get"},"req" : "note.add","file":"a\"b"}"#;
+        assert_eq!(top_level_str(body, b"req"), Some(&b"note.add"[..]));
+        assert_eq!(top_level_str(body, b"file"), Some(&br#"a\"b"#[..]));
+        assert_eq!(top_level_str(body, b"body"), None);
+        assert_eq!(
+            top_level_str(br#"{"body":{"req":"hub.get"}}"#, b"req"),
+            None
+        );
+    }
+
+    #[test]
+    fn message_without_tags() {
+        assert_eq!(
+            message("{io} i2c: corrupted {bad-bin} data {io}"),
+            "i2c: corrupted data"
+        );
+        assert_eq!(message("note not found {note-noexist}"), "note not found");
+        assert_eq!(message("{busy}"), "");
+    }
+
+    #[test]
+    fn error_response_partial_data() {
+        let emu = Emulator::new();
+        // The keys are sorted, so `err` is not the first one.
+        emu.on(
+            "card.time",
+            |_| json!({ "area": "Unknown", "err": "time is not yet set", "zone": "UTC,Unknown" }),
+        );
+
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        let r = note.card().time(&mut delay).unwrap().wait(&mut delay);
+        let Err(NoteError::NotecardErr(e)) = r else {
+            panic!("expected error response");
+        };
+        assert_eq!(e.as_str(), "time is not yet set");
+
+        let time = note.error_data::<crate::card::res::Time>().unwrap();
+        assert_eq!(time.zone.as_deref(), Some("UTC,Unknown"));
+        assert_eq!(time.time, None);
+        recovers(&mut note, &emu);
+
+        // Gone with the next response.
+        assert!(note.error_data::<crate::card::res::Time>().is_none());
+    }
+}
@@ -2,56 +2,62 @@
 
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{Deserialize, Serialize};
 
-use super::{FutureResponse, NoteError, Notecard};
+use super::{Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport::Transport;
 
-pub struct Hub<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+/// Timeout while waiting for the response to `hub.sync` (ms), as `note-c` waits for any request.
+const SYNC_TIMEOUT: u32 = 30_000;
+
+pub struct Hub<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> Hub<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> Hub<'_, IOM, BS> {
+impl<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> Hub<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> Hub<'_, IOM, BS, C, A> {
         Hub { note }
     }
 
     /// Add a "device health" log message to send to Notehub on the next sync.
     pub fn log(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         text: &str,
         alert: bool,
         sync: bool,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
-        self.note.request(
-            delay,
-            req::HubLog {
-                req: "hub.log",
-                text,
-                alert,
-                sync,
-            },
-        )?;
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
+        self.note
+            .request(delay, req::HubLog { text, alert, sync })?;
         Ok(FutureResponse::from(self.note))
     }
 
+    /// Like [`log`](Self::log), but sent as a command without waiting for a response.
+    pub fn log_cmd(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        text: &str,
+        alert: bool,
+        sync: bool,
+    ) -> Result<(), NoteError> {
+        self.note.command(delay, req::HubLog { text, alert, sync })
+    }
+
     /// The [hub.get](https://dev.blues.io/api-reference/notecard-api/hub-requests/#hub-get) request
     /// retrieves the current Notehub configuration for the Natecard.
     pub fn get(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Hub, IOM, BS>, NoteError> {
-        self.note.request_raw(delay, b"{\"req\":\"hub.get\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Hub, IOM, BS, C, A>, NoteError> {
+        let request = self.note.request_raw(delay, b"{\"req\":\"hub.get\"}\n")?;
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// The [hub.set](https://dev.blues.io/reference/notecard-api/hub-requests/#hub-set) request is
     /// the primary method for controlling the Notecard's Notehub connection and sync behavior.
     pub fn set(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         product: Option<&str>,
         host: Option<&str>,
         mode: Option<req::HubMode>,
@@ -63,11 +69,10 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> H
         vinbound: Option<&str>,
         align: Option<bool>,
         sync: Option<bool>,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::HubSet {
-                req: "hub.set",
                 product,
                 host,
                 mode,
@@ -88,15 +93,14 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> H
     /// remove the notecard from any penalty boxes.
     pub fn sync(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         allow: bool,
         out: Option<bool>,
         inn: Option<bool>,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::HubSync {
-                req: "hub.sync",
                 allow: if allow { Some(true) } else { None },
                 out,
                 inn,
@@ -106,14 +110,34 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> H
         Ok(FutureResponse::from(self.note))
     }
 
+    /// Like [`sync`](Self::sync), but sent as a command without waiting for a response. Use
+    /// [`sync_status`](Self::sync_status) to check on the sync.
+    pub fn sync_cmd(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        allow: bool,
+        out: Option<bool>,
+        inn: Option<bool>,
+    ) -> Result<(), NoteError> {
+        self.note.command(
+            delay,
+            req::HubSync {
+                allow: if allow { Some(true) } else { None },
+                out,
+                inn,
+            },
+        )
+    }
+
     /// Check on the status of a recently triggered or previous sync.
     pub fn sync_status(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::SyncStatus, IOM, BS>, NoteError> {
-        self.note
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::SyncStatus, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
             .request_raw(delay, b"{\"req\":\"hub.sync.status\"}\n")?;
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 }
 
@@ -122,8 +146,6 @@ pub mod req {
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct HubSync {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub allow: Option<bool>,
 
@@ -134,6 +156,15 @@ pub mod req {
         pub inn: Option<bool>,
     }
 
+    impl NotecardRequest for HubSync {
+        const NAME: &'static str = "hub.sync";
+        type Response = res::Empty;
+
+        fn timeout(&self) -> Option<u32> {
+            Some(SYNC_TIMEOUT)
+        }
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format)]
     #[serde(rename_all = "lowercase")]
     pub enum HubMode {
@@ -146,8 +177,6 @@ pub mod req {
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct HubSet<'a> {
-        pub req: &'static str,
-
         pub product: Option<&'a str>,
 
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -181,13 +210,22 @@ pub mod req {
         pub sync: Option<bool>,
     }
 
+    impl NotecardRequest for HubSet<'_> {
+        const NAME: &'static str = "hub.set";
+        type Response = res::Empty;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct HubLog<'a> {
-        pub req: &'static str,
         pub text: &'a str,
         pub alert: bool,
         pub sync: bool,
     }
+
+    impl NotecardRequest for HubLog<'_> {
+        const NAME: &'static str = "hub.log";
+        type Response = res::Empty;
+    }
 }
 
 pub mod res {
@@ -246,7 +284,6 @@ mod tests {
     #[test]
     pub fn hub_set_some() {
         let hb = req::HubSet {
-            req: "hub.set",
             product: Some("testprod"),
             host: Some("testhost"),
             mode: Some(req::HubMode::Periodic),
@@ -254,8 +291,35 @@ mod tests {
         };
 
         assert_eq!(
-            &serde_json_core::to_string::<_, 1024>(&hb).unwrap(),
+            &crate::ser::request_string::<_, 1024>(&hb).unwrap(),
             r#"{"req":"hub.set","product":"testprod","host":"testhost","mode":"periodic"}"#
         );
     }
+
+    #[test]
+    fn sync_timeout() {
+        use crate::emulator::fixture::{notecard, recovers};
+        use crate::emulator::{Emulator, Fault};
+        use embedded_hal_mock::eh0::delay::NoopDelay;
+
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+        let mut delay = NoopDelay::new();
+
+        // A sync takes longer than the response timeout.
+        emu.respond("hub.sync", "{}");
+        emu.inject(Fault::Slow(100));
+        note.hub()
+            .sync(&mut delay, false, None, None)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        // Other requests do not.
+        emu.inject(Fault::Slow(5));
+        let r = note.hub().sync_status(&mut delay).unwrap().wait(&mut delay);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+
+        recovers(&mut note, &emu);
+    }
 }
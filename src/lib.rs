@@ -2,7 +2,19 @@
 //! API: <https://dev.blues.io/reference/notecard-api/introduction/>
 //!
 #![feature(type_changing_struct_update)]
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+#![allow(
+    clippy::result_large_err,
+    reason = "NoteError keeps the error of the Notecard without an allocator"
+)]
+#![allow(
+    clippy::too_many_arguments,
+    reason = "the API methods take the arguments of their request"
+)]
+#![allow(
+    clippy::unnecessary_fallible_conversions,
+    reason = "From<&str> for heapless::String panics if the string does not fit"
+)]
 
 use core::convert::Infallible;
 use core::marker::PhantomData;
@@ -12,19 +24,229 @@ use defmt::{debug, error, info, trace, warn};
 
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+use embedded_hal::digital::v2::InputPin;
 use heapless::{String, Vec};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+
+use error::{BusError, DeserializeError, ErrorTag, ErrorTags, RequestName};
+use protocol::{Protocol, Step};
+use stream::Sink;
+use transport::{I2c, Transport};
+use writer::{RequestWriter, Writer};
 
 pub mod card;
 pub mod dfu;
+pub mod error;
 pub mod hub;
 pub mod note;
 pub mod web;
 pub mod ntn;
+pub mod stream;
+pub mod transport;
+
+mod crc;
+mod protocol;
+mod ser;
+mod writer;
+
+#[cfg(feature = "eh1")]
+pub mod eh1;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
+
+/// Blocking delay used while transmitting requests and waiting for responses. Implemented for any
+/// `embedded-hal` 0.2 `DelayMs<u16>` as `Delay<Eh0>`, and with the `eh1` feature for any
+/// `embedded-hal` 1.0 `DelayNs` as `Delay<Eh1>`. The driver takes delays of the same version as
+/// its transport, see [`Transport::Hal`](transport::Transport::Hal).
+pub trait Delay<HAL> {
+    fn delay_ms(&mut self, ms: u16);
+}
+
+/// `embedded-hal` 0.2, see [`Delay`].
+pub enum Eh0 {}
+
+/// `embedded-hal` 1.0, see [`Delay`].
+pub enum Eh1 {}
+
+impl<D: DelayMs<u16>> Delay<Eh0> for D {
+    fn delay_ms(&mut self, ms: u16) {
+        DelayMs::delay_ms(self, ms)
+    }
+}
+
+/// Delay using `std::thread::sleep`, enabled by the `std` feature.
+#[cfg(feature = "std")]
+pub struct StdDelay;
+
+#[cfg(feature = "std")]
+impl<HAL> Delay<HAL> for StdDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64))
+    }
+}
+
+/// The ATTN pin of the Notecard, used to sleep until the Notecard has a response or event ready,
+/// instead of polling it over the bus. Give it to the driver with [`Notecard::with_attn`] to wait
+/// on it in [`FutureResponse::wait`], or to a single [`FutureResponse::wait_attn`]. The pin is
+/// configured and armed with [`card::Card::attn`]. The driver does not re-arm it, once asserted
+/// it stays asserted and the Notecard is polled as usual until it is armed again.
+///
+/// Implemented for closures `FnMut(u32) -> u32` that wait for at most the given time (ms) for ATTN
+/// to be asserted, e.g. by sleeping until an interrupt, and for input pins with [`AttnPin`].
+pub trait Attn {
+    /// Wait for at most `timeout` ms for ATTN to be asserted. Returns the time waited (ms), which
+    /// is counted towards the timeout of the response unless the driver has a [`Clock`].
+    fn wait<HAL>(&mut self, delay: &mut impl Delay<HAL>, timeout: u32) -> u32;
+}
+
+impl<F: FnMut(u32) -> u32> Attn for F {
+    fn wait<HAL>(&mut self, _delay: &mut impl Delay<HAL>, timeout: u32) -> u32 {
+        self(timeout)
+    }
+}
+
+/// An `embedded-hal` 0.2 input pin connected to ATTN, read every `RESPONSE_DELAY` (25 ms) while
+/// waiting. Use `eh1::AttnPin` for `embedded-hal` 1.0, or a closure to sleep until an interrupt.
+pub struct AttnPin<P: InputPin>(pub P);
+
+impl<P: InputPin> Attn for AttnPin<P> {
+    fn wait<HAL>(&mut self, delay: &mut impl Delay<HAL>, timeout: u32) -> u32 {
+        // A pin that can not be read is taken as asserted, so that the Notecard is polled.
+        attn_poll(|| self.0.is_high().unwrap_or(true), delay, timeout)
+    }
+}
+
+/// Read ATTN with `high` every `RESPONSE_DELAY` until it is asserted, or for at most `timeout` ms.
+/// Returns the time waited (ms).
+pub(crate) fn attn_poll<HAL>(
+    mut high: impl FnMut() -> bool,
+    delay: &mut impl Delay<HAL>,
+    timeout: u32,
+) -> u32 {
+    let mut waited = 0;
+
+    while waited < timeout && !high() {
+        let step = (timeout - waited).min(RESPONSE_DELAY.into());
+        delay.delay_ms(step as u16);
+        waited += step;
+    }
+
+    waited
+}
+
+/// Without ATTN the Notecard is polled for the response. The default of the driver.
+pub struct NoAttn;
+
+impl Attn for NoAttn {
+    fn wait<HAL>(&mut self, _delay: &mut impl Delay<HAL>, _timeout: u32) -> u32 {
+        0
+    }
+}
+
+/// A monotonic clock used to measure timeouts, see [`Notecard::with_clock`].
+///
+/// Implemented for closures `FnMut() -> u32` returning milliseconds since some fixed point in
+/// time, wrapping around at `u32::MAX`, e.g. `|| timer.now().as_millis() as u32`.
+pub trait Clock {
+    /// Milliseconds since some fixed point in time, or `None` if there is no clock.
+    fn now_ms(&mut self) -> Option<u32>;
+}
+
+impl<F: FnMut() -> u32> Clock for F {
+    fn now_ms(&mut self) -> Option<u32> {
+        Some(self())
+    }
+}
+
+/// Without a clock only the delays of the driver are counted towards timeouts, so timeouts take
+/// longer than configured.
+pub struct NoClock;
+
+impl Clock for NoClock {
+    fn now_ms(&mut self) -> Option<u32> {
+        None
+    }
+}
+
+/// Measures the time waited for a response, with the [`Clock`] if there is one. Otherwise the
+/// time is counted from the delays, ignoring the time spent on the bus.
+pub(crate) struct Timer {
+    start: Option<u32>,
+    waited: u32,
+}
+
+impl Timer {
+    pub(crate) fn new(clock: &mut impl Clock) -> Timer {
+        Timer {
+            start: clock.now_ms(),
+            waited: 0,
+        }
+    }
+
+    /// Count a delay of `ms`.
+    pub(crate) fn delayed(&mut self, ms: u32) {
+        self.waited = self.waited.saturating_add(ms);
+    }
+
+    /// Time remaining until `timeout` (ms).
+    pub(crate) fn remaining(&self, clock: &mut impl Clock, timeout: u32) -> u32 {
+        timeout.saturating_sub(self.elapsed(clock))
+    }
+
+    /// Time elapsed since the timer was created (ms).
+    pub(crate) fn elapsed(&self, clock: &mut impl Clock) -> u32 {
+        match (self.start, clock.now_ms()) {
+            (Some(start), Some(now)) => now.wrapping_sub(start),
+            _ => self.waited,
+        }
+    }
+}
 
 /// Delay between polling for new response.
 const RESPONSE_DELAY: u16 = 25;
 
+// This is presumably limited by the notecard firmware.
+const CHUNK_LENGTH_MAX: usize = 127;
+// This is a limit that was required on some Arduinos. Can probably be increased up to
+// `CHUNK_LENGTH_MAX`, see `NotecardConfig::chunk_length`.
+const CHUNK_LENGTH: usize = 30;
+
+// `note-c` uses `250` for `SEGMENT_LENGTH`. https://github.com/blues/note-c/blob/master/n_lib.h#L40 .
+const SEGMENT_LENGTH_MAX: usize = 250;
+// Round to closest divisible by CHUNK_LENGTH so that we don't end up with unnecessarily fragmented
+// chunks.
+const SEGMENT_LENGTH: usize = (SEGMENT_LENGTH_MAX / CHUNK_LENGTH) * CHUNK_LENGTH;
+
+// The number of bytes to read is sent as a single byte.
+const READ_LENGTH_MAX: usize = 255;
+// The chunks read before `NotecardConfig::read_length` was added.
+const READ_LENGTH: usize = 126;
+
+// Used by `NotecardConfig::auto_tune` for firmware 3.4 and above, two chunks fill a segment.
+const TUNED_CHUNK_LENGTH: usize = 125;
+const TUNED_CHUNK_DELAY: u16 = 1;
+const TUNED_SEGMENT_DELAY: u16 = 10;
+
+/// Requests that can safely be sent again, see [`RetryPolicy`]. These only read state.
+const IDEMPOTENT: &[&str] = &[
+    "card.location",
+    "card.status",
+    "card.time",
+    "card.version",
+    "dfu.get",
+    "hub.get",
+    "hub.status",
+    "hub.sync.status",
+    "ntn.status",
+];
+
 /// The size of the shared request and receive buffer. Requests and responses may not serialize to
 /// any greater value than this.
 pub const DEFAULT_BUF_SIZE: usize = 18 * 1024;
@@ -34,8 +256,16 @@ pub struct NotecardConfig {
     /// I2C address of Notecard.
     pub i2c_addr: u8,
 
-    /// Timeout while waiting for response (ms).
-    pub response_timeout: u16,
+    /// Timeout while waiting for response (ms). Requests such as `hub.sync` and `web.post` wait
+    /// longer, see [`NotecardRequest::timeout`]. Can be changed for a single request with
+    /// [`FutureResponse::timeout`] or [`FutureResponse::wait_timeout`]. Without a [`Clock`], see
+    /// [`Notecard::with_clock`], only the delays of the driver are counted.
+    pub response_timeout: u32,
+
+    /// Poll the Notecard at least every `attn_poll` ms while waiting for a response on ATTN, in
+    /// case ATTN is never asserted, e.g. because it is not armed. `None` waits on ATTN until the
+    /// response times out.
+    pub attn_poll: Option<u32>,
 
     /// Delay between chunks when transmitting (ms).
     ///
@@ -53,6 +283,33 @@ pub struct NotecardConfig {
     /// > `note-c`: https://github.com/blues/note-c/blob/master/n_lib.h#L46
     /// > Original: 250 ms.
     pub segment_delay: u16,
+
+    /// Maximum length of each chunk written when transmitting, at most 127 bytes. Some I2C
+    /// peripherals can not write more than 32 bytes (including the length byte) at a time.
+    ///
+    /// > `note-c`: https://github.com/blues/note-c/blob/master/n_lib.h#L40
+    /// > Original: 30 bytes.
+    pub chunk_length: usize,
+
+    /// Length of each segment when transmitting, after which `segment_delay` is waited, at most
+    /// 250 bytes. Rounded down to a whole number of chunks.
+    pub segment_length: usize,
+
+    /// Maximum number of bytes read from the Notecard at a time when receiving a response, at most
+    /// 255 bytes. Larger reads need fewer round-trips for large responses, but some I2C
+    /// peripherals can not read more than 32 bytes (including the two byte header) at a time.
+    pub read_length: usize,
+
+    /// Request `card.version` on [`Notecard::initialize`], and use larger chunks and shorter delays
+    /// if the Notecard firmware is version 3.4 or above.
+    pub auto_tune: bool,
+
+    /// Add a sequence number and CRC to requests, and verify them on responses. A mismatch
+    /// returns [`NoteError::CrcError`]. Responses without a CRC are accepted.
+    pub crc: bool,
+
+    /// Retry policy for requests failing with transient errors.
+    pub retry: RetryPolicy,
 }
 
 impl Default for NotecardConfig {
@@ -60,12 +317,72 @@ impl Default for NotecardConfig {
         NotecardConfig {
             i2c_addr: 0x17,
             response_timeout: 5000,
+            attn_poll: None,
             chunk_delay: 20,
             segment_delay: 250,
+            chunk_length: CHUNK_LENGTH,
+            segment_length: SEGMENT_LENGTH,
+            read_length: READ_LENGTH,
+            auto_tune: false,
+            crc: false,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl NotecardConfig {
+    /// Check that the chunk, segment and read lengths are within the limits of the Notecard.
+    /// Invalid lengths are otherwise clamped to the limits when the driver is created.
+    pub fn validate(&self) -> Result<(), NoteError> {
+        if (1..=CHUNK_LENGTH_MAX).contains(&self.chunk_length)
+            && (self.chunk_length..=SEGMENT_LENGTH_MAX).contains(&self.segment_length)
+            && (1..=READ_LENGTH_MAX).contains(&self.read_length)
+        {
+            Ok(())
+        } else {
+            Err(NoteError::InvalidConfig)
         }
     }
+
+    /// Chunk, segment and read lengths, clamped to the limits of the Notecard.
+    pub(crate) fn lengths(&self) -> (usize, usize, usize) {
+        if self.validate().is_err() {
+            warn!(
+                "note: invalid chunk ({}), segment ({}) or read ({}) length, clamping.",
+                self.chunk_length, self.segment_length, self.read_length
+            );
+        }
+
+        let chunk = self.chunk_length.clamp(1, CHUNK_LENGTH_MAX);
+        let segment = self.segment_length.clamp(chunk, SEGMENT_LENGTH_MAX);
+        let read = self.read_length.clamp(1, READ_LENGTH_MAX);
+
+        (chunk, (segment / chunk) * chunk, read)
+    }
+}
+
+/// Retry policy for requests failing with a transient error (see [`NoteError::is_transient`]).
+///
+/// The driver is reset and the request is sent again, either while sending the request or while
+/// waiting for the response. Only requests that read state, such as `card.status`, `hub.get` or
+/// `note.get` without `delete`, are retried, see [`NotecardRequest::idempotent`]. Requests like
+/// `note.add`, `hub.set` or `hub.sync` would have their effect twice, and their errors are always
+/// returned.
+///
+/// The request is kept by its [`FutureResponse`] and serialized again when it is retried, so
+/// requests of any length are retried.
+#[derive(Debug, defmt::Format, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    /// Number of times a request is sent again. No retries are made by default.
+    pub retries: u8,
+
+    /// Delay before sending the request again (ms).
+    pub delay: u16,
 }
 
+/// State of the driver. A new request while waiting for or reading a response means that the
+/// [`FutureResponse`] was dropped before the response was consumed, e.g. after an error. The
+/// abandoned response is then drained before the request is sent.
 #[derive(Debug, defmt::Format)]
 pub enum NoteState {
     /// Perform handshake with Notecard.
@@ -86,11 +403,15 @@ pub enum NoteState {
 
 #[derive(Debug, defmt::Format, Clone)]
 pub enum NoteError {
-    I2cWriteError,
+    I2cWriteError(BusError),
+
+    I2cReadError(BusError),
 
-    I2cReadError,
+    SerialWriteError(BusError),
 
-    DeserError(String<256>),
+    SerialReadError(BusError),
+
+    DeserError(DeserializeError),
 
     SerError,
 
@@ -103,35 +424,170 @@ pub enum NoteError {
 
     BufOverflow,
 
+    /// Sequence number or CRC of response does not match the request.
+    CrcError,
+
     /// Method called when notecarrier is in invalid state.
     WrongState,
 
+    /// Chunk or segment length in [`NotecardConfig`] outside the limits of the Notecard.
+    InvalidConfig,
+
     /// Notecard firmware is being updated.
-    DFUInProgress,
+    DFUInProgress(NotecardError),
 
     /// Notecard is in NTN mode and regular packages can't be added.
-    NonPortNoteInPackageMode,
+    NonPortNoteInPackageMode(NotecardError),
 
     /// Notecard filesystem full
-    FileStorageFull(String<256>),
+    FileStorageFull(NotecardError),
 
     /// Error Adding Note
-    ErrorAddingNote(String<256>),
+    ErrorAddingNote(NotecardError),
 
-    NotecardErr(String<256>),
+    /// Any other error response of the Notecard, see [`NotecardError::tags`].
+    NotecardErr(NotecardError),
 }
 
 impl NoteError {
     pub fn new_desererror(msg: &[u8]) -> NoteError {
-        let msg = core::str::from_utf8(msg).unwrap_or("[invalid utf-8]");
-        let mut s = String::new();
-        s.push_str(msg).ok();
-        NoteError::DeserError(s)
+        NoteError::DeserError(DeserializeError::new(msg))
     }
 
     pub fn string_err(_e: Infallible) -> NoteError {
         NoteError::BufOverflow
     }
+
+    /// Transient errors, caused by the bus or the Notecard being busy, that may succeed when the
+    /// request is retried.
+    pub fn is_transient(&self) -> bool {
+        use NoteError::*;
+
+        match self {
+            I2cWriteError(e) | I2cReadError(e) | SerialWriteError(e) | SerialReadError(e) => {
+                e.is_transient()
+            }
+            RemainingData | TimeOut | CrcError => true,
+            _ => self.tags().contains(ErrorTag::Io),
+        }
+    }
+
+    /// The name of the request that failed, for error responses of the Notecard and responses
+    /// that could not be deserialized.
+    pub fn request(&self) -> Option<&str> {
+        use NoteError::*;
+
+        let request = match self {
+            DFUInProgress(e)
+            | NonPortNoteInPackageMode(e)
+            | FileStorageFull(e)
+            | ErrorAddingNote(e)
+            | NotecardErr(e) => &e.request,
+            DeserError(e) => &e.request,
+            _ => return None,
+        };
+
+        Some(request.as_str()).filter(|r| !r.is_empty())
+    }
+
+    /// Attach the name of the failing request to the error, see [`request`](Self::request).
+    pub(crate) fn in_request(mut self, name: &RequestName) -> NoteError {
+        use NoteError::*;
+
+        match &mut self {
+            DFUInProgress(e)
+            | NonPortNoteInPackageMode(e)
+            | FileStorageFull(e)
+            | ErrorAddingNote(e)
+            | NotecardErr(e) => e.request = name.clone(),
+            DeserError(e) => e.request = name.clone(),
+            _ => {}
+        }
+
+        self
+    }
+
+    /// The kind of a bus error, to e.g. tell a Notecard that does not acknowledge from a fault on
+    /// the bus.
+    pub fn bus_error(&self) -> Option<BusError> {
+        use NoteError::*;
+
+        match self {
+            I2cWriteError(e) | I2cReadError(e) | SerialWriteError(e) | SerialReadError(e) => {
+                Some(*e)
+            }
+            _ => None,
+        }
+    }
+
+    /// The tags of an error response of the Notecard, empty for other errors.
+    pub fn tags(&self) -> ErrorTags {
+        use NoteError::*;
+
+        match self {
+            DFUInProgress(e)
+            | NonPortNoteInPackageMode(e)
+            | FileStorageFull(e)
+            | ErrorAddingNote(e)
+            | NotecardErr(e) => e.tags(),
+            _ => ErrorTags::default(),
+        }
+    }
+}
+
+impl core::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use NoteError::*;
+
+        match self {
+            I2cWriteError(e) => write!(f, "I2C write failed: {e}"),
+            I2cReadError(e) => write!(f, "I2C read failed: {e}"),
+            SerialWriteError(e) => write!(f, "serial write failed: {e}"),
+            SerialReadError(e) => write!(f, "serial read failed: {e}"),
+            DeserError(e) => e.fmt(f),
+            SerError => f.write_str("failed to serialize request"),
+            InvalidRequest => f.write_str("invalid request"),
+            RemainingData => f.write_str("Notecard sent data when queried for available data"),
+            TimeOut => f.write_str("timed out waiting for response"),
+            BufOverflow => f.write_str("buffer overflow"),
+            CrcError => f.write_str("sequence number or CRC of response does not match"),
+            WrongState => f.write_str("driver is in the wrong state"),
+            InvalidConfig => {
+                f.write_str("chunk or segment length outside the limits of the Notecard")
+            }
+            DFUInProgress(e)
+            | NonPortNoteInPackageMode(e)
+            | FileStorageFull(e)
+            | ErrorAddingNote(e)
+            | NotecardErr(e) => e.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for NoteError {}
+
+/// The firmware version reported by `card.version`.
+#[derive(Deserialize)]
+struct FirmwareVersion {
+    body: FirmwareVersionBody,
+}
+
+#[derive(Deserialize)]
+struct FirmwareVersionBody {
+    ver_major: u8,
+    ver_minor: u8,
+}
+
+impl FirmwareVersion {
+    /// Firmware 3.4 and above accepts requests faster, see [`NotecardConfig::auto_tune`].
+    fn tunable(&self) -> bool {
+        (self.body.ver_major, self.body.ver_minor) >= (3, 4)
+    }
+}
+
+/// The name of a serialized request, e.g. `card.status`: the `req` key of its top-level object.
+pub(crate) fn request_name(request: &[u8]) -> Option<&str> {
+    core::str::from_utf8(error::top_level_str(request, b"req")?).ok()
 }
 
 pub(crate) fn str_string<const N: usize>(
@@ -142,560 +598,970 @@ pub(crate) fn str_string<const N: usize>(
         .map_err(NoteError::string_err)
 }
 
-#[derive(Deserialize, Debug, defmt::Format)]
+/// A request to the Notecard, and the type of its response. The request is serialized as a JSON
+/// object with its name added as the `req` field, so it must serialize to an object, e.g. a struct
+/// with named fields.
+///
+/// The requests in the API modules, like [`card::req::Attn`], implement it. It can be implemented
+/// for requests that are not wrapped by the API, and sent with [`Notecard::send`]:
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct Temp {}
+///
+/// #[derive(Deserialize)]
+/// struct TempResponse {
+///     value: f32,
+/// }
+///
+/// impl NotecardRequest for Temp {
+///     const NAME: &'static str = "card.temp";
+///     type Response = TempResponse;
+/// }
+///
+/// let temp = note.send(&mut delay, Temp {})?.wait(&mut delay)?;
+/// ```
+pub trait NotecardRequest: Serialize {
+    /// Name of the request, e.g. `card.temp`.
+    const NAME: &'static str;
+
+    /// The response to the request.
+    type Response: DeserializeOwned;
+
+    /// Timeout while waiting for the response (ms), for requests that take longer than the
+    /// `response_timeout` of the [`NotecardConfig`], which is used if it is longer.
+    fn timeout(&self) -> Option<u32> {
+        None
+    }
+
+    /// Whether the request only reads state, and can safely be sent again after a transient error,
+    /// see [`RetryPolicy`]. True for getters like `card.status` and `hub.get`.
+    fn idempotent(&self) -> bool {
+        IDEMPOTENT.contains(&Self::NAME)
+    }
+}
+
+/// A request kept by a [`FutureResponse`] to be written again when it is retried, see
+/// [`RetryPolicy`]. Implemented for any [`NotecardRequest`], which is serialized again, and for raw
+/// requests.
+pub trait Resend {
+    /// Write the request to `sink`, without the newline that ends it.
+    fn resend(&self, sink: &mut impl Sink) -> Result<(), NoteError>;
+}
+
+impl<R: NotecardRequest> Resend for R {
+    fn resend(&self, sink: &mut impl Sink) -> Result<(), NoteError> {
+        ser::request_to_sink(self, sink)
+    }
+}
+
+impl Resend for &[u8] {
+    fn resend(&self, sink: &mut impl Sink) -> Result<(), NoteError> {
+        sink.write(self)
+    }
+}
+
+/// An error response of the Notecard. The error string may contain tags such as `{io}` that
+/// classify the error, see [`error`].
+#[derive(Deserialize, Debug, defmt::Format, Clone)]
 pub struct NotecardError {
     err: String<256>,
+
+    /// The name of the request, see [`NoteError::request`].
+    #[serde(skip)]
+    request: RequestName,
+}
+
+impl core::fmt::Display for NotecardError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if !self.request.is_empty() {
+            write!(f, "{}: ", self.request)?;
+        }
+        f.write_str(&self.err)
+    }
+}
+
+impl NotecardError {
+    /// The error string as it was received, including the tags.
+    pub fn as_str(&self) -> &str {
+        &self.err
+    }
+
+    /// The error string without its tags.
+    pub fn message(&self) -> String<256> {
+        error::message(&self.err)
+    }
+
+    /// The tags of the error.
+    pub fn tags(&self) -> ErrorTags {
+        ErrorTags::parse(&self.err)
+    }
+
+    /// The names of all tags of the error without braces, including those that are
+    /// [`ErrorTag::Other`].
+    pub fn tag_names(&self) -> impl Iterator<Item = &str> {
+        error::tag_names(&self.err)
+    }
 }
 
 impl From<NotecardError> for NoteError {
     fn from(n: NotecardError) -> NoteError {
-        if n.err.contains("{dfu-in-progress}") {
-            NoteError::DFUInProgress
+        let tags = n.tags();
+
+        if tags.contains(ErrorTag::DfuInProgress) {
+            NoteError::DFUInProgress(n)
         } else if n.err.contains("adding notes to a non-uplinked port is not allowed") {
-            NoteError::NonPortNoteInPackageMode
-        } else if n.err.contains("{file-storage-full}") {
-            NoteError::FileStorageFull(n.err)
+            NoteError::NonPortNoteInPackageMode(n)
+        } else if tags.contains(ErrorTag::FileStorageFull) {
+            NoteError::FileStorageFull(n)
         } else if n.err.contains("error adding note") {
-            NoteError::ErrorAddingNote(n.err)
+            NoteError::ErrorAddingNote(n)
         } else {
-            NoteError::NotecardErr(n.err)
+            NoteError::NotecardErr(n)
         }
     }
 }
 
+/// Deserialize a response body, or the error if the Notecard responded with an error. If `crc` is
+/// the sequence number of the request, the sequence number and CRC of the response are checked.
+pub(crate) fn deserialize_response<T: DeserializeOwned>(
+    body: &[u8],
+    crc: Option<u16>,
+) -> Result<T, NoteError> {
+    check_response(body, crc)?;
+
+    trace!("response is regular, parsing..");
+    Ok(serde_json_core::from_slice::<T>(body)
+        .map_err(|_| {
+            error!(
+                "failed to deserialize: {}",
+                core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
+            );
+            NoteError::new_desererror(body)
+        })?
+        .0)
+}
+
+/// Check the sequence number and CRC of a response, if enabled, and return an error response as
+/// [`NoteError::NotecardErr`].
+pub(crate) fn check_response(body: &[u8], crc: Option<u16>) -> Result<(), NoteError> {
+    if let Some(seq) = crc {
+        crc::check(body, seq)?;
+    }
+
+    if error::is_error_response(body) {
+        Err(notecard_error(body))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that a raw request is a single line terminated by a newline, and return it without the
+/// newline.
+pub(crate) fn raw_request(cmd: &[u8]) -> Result<&[u8], NoteError> {
+    match cmd.split_last() {
+        Some((b'\n', cmd)) if !cmd.contains(&b'\n') => Ok(cmd),
+        _ => {
+            error!("note: raw request must be a single line ending with a newline.");
+            Err(NoteError::InvalidRequest)
+        }
+    }
+}
+
+/// Whether a raw request, without its newline, can safely be sent again, see [`IDEMPOTENT`].
+pub(crate) fn raw_idempotent(cmd: &[u8]) -> bool {
+    request_name(cmd).is_some_and(|name| IDEMPOTENT.contains(&name))
+}
+
+/// The error of an error response.
+pub(crate) fn notecard_error(body: &[u8]) -> NoteError {
+    debug!(
+        "response is error response, parsing error..: {}",
+        core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
+    );
+    serde_json_core::from_slice::<NotecardError>(body).map_or_else(
+        |_| {
+            error!(
+                "failed to deserialize: {}",
+                core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
+            );
+            NoteError::new_desererror(body)
+        },
+        |(e, _)| NoteError::from(e),
+    )
+}
+
 /// The driver for the Notecard. Must be intialized before making any requests.
 pub struct Notecard<
-    IOM: Write<SevenBitAddress> + Read<SevenBitAddress>,
+    IOM: Transport,
     const BUF_SIZE: usize = DEFAULT_BUF_SIZE,
+    C: Clock = NoClock,
+    A: Attn = NoAttn,
 > {
-    i2c: IOM,
-    addr: u8,
-    state: NoteState,
+    iom: IOM,
 
-    /// The receive buffer. Must be large enough to hold the largest response that will be received.
-    buf: Vec<u8, BUF_SIZE>,
+    /// Requests are never kept in the buffer, so that the driver can be reset before a request
+    /// without copying it.
+    proto: Protocol<BUF_SIZE>,
 
-    response_timeout: u16,
-    chunk_delay: u16,
-    segment_delay: u16,
+    clock: C,
+    attn: A,
 }
 
-pub struct SuspendState<const BUF_SIZE: usize> {
-    addr: u8,
-    state: NoteState,
-    buf: Vec<u8, BUF_SIZE>,
-    response_timeout: u16,
-    chunk_delay: u16,
-    segment_delay: u16,
+pub struct SuspendState<const BUF_SIZE: usize, C: Clock = NoClock, A: Attn = NoAttn> {
+    proto: Protocol<BUF_SIZE>,
+    clock: C,
+    attn: A,
 }
 
 impl<IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BUF_SIZE: usize>
-    Notecard<IOM, BUF_SIZE>
+    Notecard<I2c<IOM>, BUF_SIZE>
 {
-    pub fn new(i2c: IOM) -> Notecard<IOM, BUF_SIZE> {
+    pub fn new(i2c: IOM) -> Notecard<I2c<IOM>, BUF_SIZE> {
         Self::new_with_config(i2c, NotecardConfig::default())
     }
 
-    pub fn new_with_config(i2c: IOM, c: NotecardConfig) -> Notecard<IOM, BUF_SIZE> {
-        Notecard {
-            i2c,
-            addr: c.i2c_addr,
-            state: NoteState::Handshake,
-            buf: Vec::new(),
+    pub fn new_with_config(i2c: IOM, c: NotecardConfig) -> Notecard<I2c<IOM>, BUF_SIZE> {
+        let i2c = I2c::new(i2c, c.i2c_addr);
+        Self::new_with_transport(i2c, c)
+    }
+}
 
-            response_timeout: c.response_timeout,
-            chunk_delay: c.chunk_delay,
-            segment_delay: c.segment_delay,
+impl<IOM: Transport, const BUF_SIZE: usize> Notecard<IOM, BUF_SIZE> {
+    /// Create a driver for a Notecard connected through `iom`, e.g. a [`transport::Serial`]. The
+    /// `i2c_addr` of the config is not used, it is given to [`transport::I2c`] directly.
+    pub fn new_with_transport(iom: IOM, c: NotecardConfig) -> Notecard<IOM, BUF_SIZE> {
+        Notecard {
+            iom,
+            proto: Protocol::new(&c),
+            clock: NoClock,
+            attn: NoAttn,
         }
     }
+}
+
+impl<IOM: Transport, const BUF_SIZE: usize, C: Clock, A: Attn> Notecard<IOM, BUF_SIZE, C, A> {
+    /// Measure timeouts with a monotonic `clock`, consuming the existing, and returning a new
+    /// Notecard instance. Without a clock only the delays of the driver are counted, so timeouts
+    /// take longer than configured:
+    ///
+    /// ```ignore
+    /// let mut note = Notecard::new(i2c).with_clock(|| timer.now().as_millis() as u32);
+    /// ```
+    pub fn with_clock<C2: Clock>(self, clock: C2) -> Notecard<IOM, BUF_SIZE, C2, A> {
+        Notecard { clock, ..self }
+    }
+
+    /// Wait for responses on the ATTN pin, consuming the existing, and returning a new Notecard
+    /// instance. ATTN must be configured with [`card::Card::attn`] first, see [`Attn`]:
+    ///
+    /// ```ignore
+    /// let mut note = Notecard::new(i2c).with_attn(AttnPin(attn));
+    /// note.card().attn(&mut delay, Some("arm"), None)?.wait(&mut delay)?;
+    /// ```
+    pub fn with_attn<A2: Attn>(self, attn: A2) -> Notecard<IOM, BUF_SIZE, C, A2> {
+        Notecard { attn, ..self }
+    }
 
     /// Resize the internal buffer, consuming the existing, and returning a new Notecard
     /// instance.
-    pub fn resize_buf<const B: usize>(self) -> Result<Notecard<IOM, B>, NoteError> {
-        if B < self.buf.len() {
-            Err(NoteError::BufOverflow)
-        } else {
-            Ok(Notecard {
-                buf: Vec::<_, B>::from_slice(&self.buf).unwrap(),
-                ..self
-            })
-        }
+    pub fn resize_buf<const B: usize>(self) -> Result<Notecard<IOM, B, C, A>, NoteError> {
+        Ok(Notecard {
+            iom: self.iom,
+            proto: self.proto.resize()?,
+            clock: self.clock,
+            attn: self.attn,
+        })
     }
 
-    /// Free the IOM device and return the driver state so that it can be quickly resumed. It is
+    /// Free the transport and return the driver state so that it can be quickly resumed. It is
     /// not safe to change the state of the Notecard in the meantime, or create a second driver
     /// without using this state.
-    pub fn suspend(self) -> (IOM, SuspendState<BUF_SIZE>) {
+    pub fn suspend(self) -> (IOM, SuspendState<BUF_SIZE, C, A>) {
         (
-            self.i2c,
+            self.iom,
             SuspendState {
-                state: self.state,
-                buf: self.buf,
-                addr: self.addr,
-                response_timeout: self.response_timeout,
-                chunk_delay: self.chunk_delay,
-                segment_delay: self.segment_delay,
+                proto: self.proto,
+                clock: self.clock,
+                attn: self.attn,
             },
         )
     }
 
     /// Resume a previously [`suspend`]ed Notecard driver.
-    pub fn resume(i2c: IOM, state: SuspendState<BUF_SIZE>) -> Notecard<IOM, BUF_SIZE> {
+    pub fn resume(iom: IOM, state: SuspendState<BUF_SIZE, C, A>) -> Notecard<IOM, BUF_SIZE, C, A> {
         Notecard {
-            i2c,
-            addr: state.addr,
-            state: state.state,
-            buf: state.buf,
-            response_timeout: state.response_timeout,
-            chunk_delay: state.chunk_delay,
-            segment_delay: state.segment_delay,
+            iom,
+            proto: state.proto,
+            clock: state.clock,
+            attn: state.attn,
         }
     }
 
-    /// Initialize the notecard driver by performing handshake with notecard.
-    pub fn initialize(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+    /// Initialize the notecard driver by performing handshake with notecard, and [`tune`] it if
+    /// [`NotecardConfig::auto_tune`] is set.
+    ///
+    /// [`tune`]: Self::tune
+    pub fn initialize(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
         info!("note: initializing.");
-        self.reset(delay)
+        self.reset(delay)?;
+
+        if self.proto.auto_tune {
+            self.tune(delay)?;
+        }
+
+        Ok(())
+    }
+
+    /// Request `card.version`, and use larger chunks and shorter delays when transmitting if the
+    /// Notecard firmware is version 3.4 or above. Otherwise the configuration is left unchanged.
+    pub fn tune(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
+        let request = self.request_raw(delay, b"{\"req\":\"card.version\"}\n")?;
+        let version: FirmwareVersion =
+            FutureResponse::from(&mut *self).keep(request).wait(delay)?;
+        self.proto.tune(&version);
+
+        Ok(())
+    }
+
+    /// Partial data of the last response, deserialized as `D`, if it was an error response. E.g.
+    /// `card.time` answers with the `zone` before the time has been set:
+    ///
+    /// ```ignore
+    /// match note.card().time(&mut delay)?.wait(&mut delay) {
+    ///     Err(NoteError::NotecardErr(_)) => note.error_data::<Time>().and_then(|t| t.zone),
+    ///     r => r?.zone,
+    /// }
+    /// ```
+    ///
+    /// The error response is kept in the buffer of the driver until the next request. Returns
+    /// `None` if there is none, or it does not deserialize as `D`.
+    pub fn error_data<D: DeserializeOwned>(&self) -> Option<D> {
+        self.proto.error_data()
     }
 
     /// Check if notecarrier is connected and responding.
     ///
     /// > This is allowed no matter the state.
     pub fn ping(&mut self) -> bool {
-        self.i2c.write(self.addr, &[]).is_ok()
+        self.iom.ping()
     }
 
     /// Query the notecard for available bytes.
     pub fn data_query(&mut self) -> Result<usize, NoteError> {
-        trace!("note: data_query: {:?}", self.state);
-        if !matches!(self.state, NoteState::Response(_)) {
-            // Ask for reading, but with zero bytes allocated.
-            self.i2c
-                .write(self.addr, &[0, 0])
-                .map_err(|_| NoteError::I2cWriteError)?;
-
-            let mut buf = [0u8; 2];
-
-            // Read available bytes to read
-            self.i2c
-                .read(self.addr, &mut buf)
-                .map_err(|_| NoteError::I2cReadError)?;
-
-            let available = buf[0] as usize;
-            let sent = buf[1] as usize;
-
-            if available > 0 {
-                self.buf.clear();
-                self.state = NoteState::Response(available);
-            }
-
-            trace!("avail = {}, sent = {}", available, sent);
-
-            if sent > 0 {
-                error!(
-                    "data query: bytes sent when querying available bytes: {}",
-                    sent
-                );
-                Err(NoteError::RemainingData)
-            } else {
-                Ok(available)
-            }
-        } else {
-            error!("note: data_query called while reading response.");
-            Err(NoteError::WrongState)
-        }
+        self.proto.query()?;
+        let available = self.iom.data_query()?;
+        Ok(self.proto.queried(available))
     }
 
-    /// Read until empty.
-    fn read(&mut self) -> Result<usize, NoteError> {
-        if let NoteState::Response(avail) = self.state {
-            // Chunk to read + notecard header (2 bytes)
-            let mut bytes = Vec::<u8, 128>::new();
-
-            let sz = (bytes.capacity() - 2).min(avail);
-            bytes.resize(sz + 2, 0).unwrap();
-
-            debug!("asking to read: {} of available {} bytes", sz, avail);
-
-            // Ask for reading `sz` bytes
-            self.i2c
-                .write(self.addr, &[0, sz as u8])
-                .map_err(|_| NoteError::I2cWriteError)?;
-
-            // Read bytes
-            self.i2c
-                .read(self.addr, &mut bytes)
-                .map_err(|_| NoteError::I2cReadError)?;
-
-            let available = bytes[0] as usize;
-            let sent = bytes[1] as usize;
-
-            self.buf.extend_from_slice(&bytes[2..]).unwrap(); // XXX: check enough space
-
-            trace!("read:  {}", unsafe {
-                core::str::from_utf8_unchecked(&bytes)
-            });
-
-            trace!("avail = {}, sent = {}", available, sent);
+    /// Poll for data.
+    fn poll(&mut self) -> Result<Option<&[u8]>, NoteError> {
+        loop {
+            match self.proto.step()? {
+                Step::Query => {
+                    // 1. Check for available data
+                    let sz = self.data_query()?;
+                    if sz > 0 {
+                        debug!("response ready: {} bytes..", sz);
+                    } else {
+                        // sleep and wait for ready.
+                        return Ok(None);
+                    }
+                }
+                Step::Read(avail) => {
+                    let (len, buf) = self.proto.read_buf()?;
+                    let r = self.iom.read(avail, buf);
+                    if self.proto.read(len, r)? > 0 {
+                        // sleep and wait for more data.
+                        return Ok(None);
+                    }
+                }
+                Step::Discard(avail) => {
+                    let r = self.iom.read(avail, self.proto.discard_buf()?);
+                    if self.proto.discarded(r)? > 0 {
+                        return Ok(None);
+                    }
 
-            if available > 0 {
-                self.state = NoteState::Response(available);
-            } else {
-                self.state = NoteState::ResponseReady;
+                    return Err(NoteError::BufOverflow);
+                }
+                Step::Ready => return Ok(Some(self.proto.take_response()?)),
             }
-
-            Ok(available)
-        } else {
-            error!("read: called when not waiting for response");
-            Err(NoteError::WrongState)
         }
     }
 
-    /// Take the response from the buffer. Once this function has been called, the state is reset
-    /// and it is no longer safe to read the buffer.
-    ///
-    /// Safety:
-    ///
-    /// This function returns an immutable reference to the buffer, but new requests require a
-    /// mutable reference to `Note`. This is not granted before the immutable reference is
-    /// released.
-    fn take_response(&mut self) -> Result<&[u8], NoteError> {
-        if matches!(self.state, NoteState::ResponseReady) {
-            self.state = NoteState::Request;
-
-            Ok(&self.buf)
-        } else {
-            error!("take response called when response not ready");
-            Err(NoteError::WrongState)
+    /// Poll for data like [`poll`](Self::poll), but read the response into `chunk` instead of the
+    /// buffer, which must have room for `read_length` bytes and the header of the transport.
+    /// Returns the bytes read, and whether the response is complete.
+    fn poll_chunk<'c>(&mut self, chunk: &'c mut [u8]) -> Result<(&'c [u8], bool), NoteError> {
+        if let Step::Query = self.proto.chunk_step()?
+            && self.data_query()? == 0
+        {
+            return Ok((&[], false));
         }
-    }
 
-    /// Poll for data.
-    fn poll(&mut self) -> Result<Option<&[u8]>, NoteError> {
-        trace!("note: poll: {:?}", self.state);
-        match self.state {
-            NoteState::Poll(_) => {
-                // 1. Check for available data
-                let sz = self.data_query()?;
-                if sz > 0 {
-                    debug!("response ready: {} bytes..", sz);
-
-                    self.poll()
-                } else {
-                    // sleep and wait for ready.
-                    Ok(None)
-                }
-            }
-            NoteState::Response(_) => {
-                let avail = self.read()?;
-                if avail == 0 {
-                    self.poll()
-                } else {
-                    // sleep and wait for more data.
-                    Ok(None)
-                }
-            }
-            NoteState::ResponseReady => {
-                debug!("response read, deserializing.");
-                Ok(Some(self.take_response()?))
-            }
-            _ => {
-                error!("poll called when not receiving response");
-                Err(NoteError::WrongState)
+        match self.proto.chunk_step()? {
+            Step::Read(avail) => {
+                let (sz, available) = self.iom.read(avail, chunk)?;
+                self.proto.chunk_read(available);
+
+                Ok((&chunk[..sz], available == 0))
             }
+            _ => Err(NoteError::WrongState),
         }
     }
 
     /// Read any remaining data from the Notecarrier. This will cancel any waiting responses, and
     /// waiting for a response after this call will time-out.
-    unsafe fn consume_response(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
+    fn consume_response(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
         warn!("note: trying to consume any left-over response.");
-        let mut waited = 0;
+        let mut timer = Timer::new(&mut self.clock);
 
-        while waited < self.response_timeout {
+        while timer.elapsed(&mut self.clock) < self.proto.response_timeout {
             if (self.poll()?).is_some() {
-                self.buf.clear();
+                self.proto.buf.clear();
                 return Ok(());
             }
 
             delay.delay_ms(RESPONSE_DELAY);
-            waited += RESPONSE_DELAY;
+            timer.delayed(RESPONSE_DELAY.into());
         }
 
-        self.buf.clear();
+        self.proto.buf.clear();
 
-        error!("response timed out (>= {}).", self.response_timeout);
+        error!("response timed out (>= {}).", self.proto.response_timeout);
         Err(NoteError::TimeOut)
     }
 
     /// Reset notecard driver and state. Any waiting responses will be invalidated
     /// and time-out. However, you won't be able to get a mutable reference without having
     /// dropped the `FutureResponse`.
-    pub fn reset(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
-        warn!("resetting: consuming any left-over response and perform a new handshake.");
-
-        self.buf.clear(); // clear in case data_query() is 0.
-        self.state = NoteState::Handshake;
+    pub fn reset(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
+        self.proto.reset();
         self.handshake(delay)
     }
 
-    fn handshake(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
-        if matches!(self.state, NoteState::Handshake) {
+    fn handshake(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
+        if matches!(self.proto.state, NoteState::Handshake) {
             debug!("note: handshake");
             if self.data_query()? > 0 {
                 error!("note: handshake: remaining data in queue, consuming..");
-                unsafe { self.consume_response(delay)? };
+                self.consume_response(delay)?;
             }
 
-            self.state = NoteState::Request;
+            self.proto.answered();
         }
         Ok(())
     }
 
-    /// Sends request from buffer.
-    fn send_request(&mut self, delay: &mut impl DelayMs<u16>) -> Result<(), NoteError> {
-        // This is presumably limited by the notecard firmware.
-        const CHUNK_LENGTH_MAX: usize = 127;
-        // This is a limit that was required on some Arduinos. Can probably be increased up to
-        // `CHUNK_LENGTH_MAX`. Should maybe be configurable.
-        const CHUNK_LENGTH_I: usize = 30;
-        const CHUNK_LENGTH: usize = if CHUNK_LENGTH_I < CHUNK_LENGTH_MAX {
-            CHUNK_LENGTH_I
-        } else {
-            CHUNK_LENGTH_MAX
-        };
-
-        // `note-c` uses `250` for `SEGMENT_LENGTH`. Round to closest divisible
-        // by CHUNK_LENGTH so that we don't end up with unnecessarily fragmented
-        // chunks. https://github.com/blues/note-c/blob/master/n_lib.h#L40 .
-        const SEGMENT_LENGTH: usize = (250 / CHUNK_LENGTH) * CHUNK_LENGTH;
+    /// Make the driver ready for a new request. The response to a previous request that was
+    /// abandoned by dropping its [`FutureResponse`] is drained, so that it is not mistaken for the
+    /// response to the new request. If it never arrives the driver is reset.
+    fn ready(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
+        if self.proto.abandoned() {
+            if let Err(e) = self.consume_response(delay) {
+                warn!("note: failed to drain abandoned response: {:?}", e);
+                return self.reset(delay);
+            }
 
-        if !matches!(self.state, NoteState::Request) {
-            warn!("note: request: wrong-state, resetting before new request.");
-            let buf = self.buf.clone();
+            self.proto.answered();
+        }
 
-            self.reset(delay)?; // this clears the buffer - and may use it for other stuff!
+        Ok(())
+    }
 
-            self.buf.clear();
-            self.buf
-                .resize(buf.len(), 0)
-                .map_err(|_| NoteError::BufOverflow)?;
-            self.buf.copy_from_slice(&buf);
+    /// Reset the driver before a request if it is in the wrong state, or if a stale response is
+    /// waiting after the last request was not answered. The reset may read into the buffer, so
+    /// this is done before the request is written.
+    fn prepare(&mut self, delay: &mut impl Delay<IOM::Hal>) -> Result<(), NoteError> {
+        if matches!(self.proto.state, NoteState::Request)
+            && self.proto.unanswered
+            && self.iom.data_query()? > 0
+        {
+            warn!("note: request: stale response waiting, resetting before new request.");
+            self.proto.state = NoteState::Handshake;
         }
 
-        if self.buf.last() != Some(&b'\n') {
-            return Err(NoteError::InvalidRequest);
+        if !matches!(self.proto.state, NoteState::Request) {
+            warn!("note: request: wrong-state, resetting before new request.");
+            self.reset(delay)?;
         }
 
-        trace!("note: making request: {}", unsafe {
-            core::str::from_utf8_unchecked(&self.buf)
-        });
+        Ok(())
+    }
 
-        let mut buf = Vec::<u8, { CHUNK_LENGTH + 1 }>::new();
-        for segment in self.buf.chunks(SEGMENT_LENGTH) {
-            for c in segment.chunks(buf.capacity() - 1) {
-                buf.push(c.len() as u8).unwrap();
-                buf.extend_from_slice(c).unwrap();
+    /// Write a request, or a command, to the Notecard while it is produced by `write`. Errors
+    /// while preparing the driver are returned after the request has been started, so that it can
+    /// be retried.
+    fn stream<D: Delay<IOM::Hal>>(
+        &mut self,
+        delay: &mut D,
+        command: bool,
+        request: RequestName,
+        timeout: Option<u32>,
+        write: impl FnOnce(&mut RequestWriter<Writer<'_, IOM, D>>) -> Result<(), NoteError>,
+    ) -> Result<(), NoteError> {
+        let prepared = self.prepare(delay);
+        let seq = self.proto.begin(request, timeout);
+        prepared?;
 
-                trace!("note: sending chunk: {} => {}", &buf, unsafe {
-                    core::str::from_utf8_unchecked(&buf)
-                });
+        self.write_request(delay, command, seq, write)
+    }
 
-                self.i2c
-                    .write(self.addr, &buf)
-                    .map_err(|_| NoteError::I2cWriteError)?;
+    /// Write a request with the sequence number `seq`, produced by `write`.
+    fn write_request<D: Delay<IOM::Hal>>(
+        &mut self,
+        delay: &mut D,
+        command: bool,
+        seq: Option<u16>,
+        write: impl FnOnce(&mut RequestWriter<Writer<'_, IOM, D>>) -> Result<(), NoteError>,
+    ) -> Result<(), NoteError> {
+        let pacing = self.proto.pacing();
+        let writer = Writer::new(&mut self.iom, delay, self.proto.chunk_length, pacing);
+        let mut request = RequestWriter::new(writer, command, seq);
 
-                buf.clear();
-                delay.delay_ms(self.chunk_delay);
+        write(&mut request)?;
+        request.finish()?;
+        request.into_inner().finish()
+    }
+
+    /// Reset and send the kept `request` again after the transient error `e`, if allowed by the
+    /// retry policy. Otherwise `e` is returned.
+    fn retry(
+        &mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        mut e: NoteError,
+        request: Option<&impl Resend>,
+    ) -> Result<(), NoteError> {
+        let Some(request) = request else {
+            return Err(e);
+        };
+
+        while self.proto.retrying(&e) {
+            delay.delay_ms(self.proto.retry_policy.delay);
+
+            if let Err(err) = self.reset(delay) {
+                e = err;
+                continue;
             }
-            delay.delay_ms(self.segment_delay);
-        }
 
-        self.state = NoteState::Poll(0);
+            // The request is sent with the same sequence number.
+            let seq = self.proto.response_seq();
 
-        Ok(())
+            match self.write_request(delay, false, seq, |w| request.resend(w)) {
+                Ok(()) => {
+                    self.proto.sent();
+                    return Ok(());
+                }
+                Err(err) => e = err,
+            }
+        }
+
+        Err(e)
     }
 
-    /// Make a raw request. The byte slice must end with `\n`. After making a request a
-    /// [FutureResponse] must be created and consumed.
-    pub(crate) fn request_raw(
+    /// Make a raw request. The byte slice must be a single line ending with `\n`. After making a
+    /// request a [FutureResponse] must be created and consumed. The request is returned without
+    /// the newline if it is kept for retrying, see [`FutureResponse::keep`].
+    pub(crate) fn request_raw<'r>(
         &mut self,
-        delay: &mut impl DelayMs<u16>,
-        cmd: &[u8],
-    ) -> Result<(), NoteError> {
-        self.buf.clear();
-        self.buf
-            .resize(cmd.len(), 0)
-            .map_err(|_| NoteError::BufOverflow)?;
-
-        self.buf.copy_from_slice(cmd);
+        delay: &mut impl Delay<IOM::Hal>,
+        cmd: &'r [u8],
+    ) -> Result<Option<&'r [u8]>, NoteError> {
+        let cmd = raw_request(cmd)?;
+        self.ready(delay)?;
+        let name = error::new_request_name(request_name(cmd));
+        let kept = self.proto.keeps(raw_idempotent(cmd)).then_some(cmd);
+
+        match self.stream(delay, false, name, None, |request| request.write(cmd)) {
+            Ok(()) => self.proto.sent(),
+            Err(e) => self.retry(delay, e, kept.as_ref())?,
+        }
 
-        self.send_request(delay)
+        Ok(kept)
     }
 
     /// Make a request. After making a request a [FutureResponse] must be created and consumed
     /// before making any new requests. This method is usually called through the API methods like
-    /// `[card]`.
-    pub(crate) fn request<T: Serialize>(
+    /// `[card]`. The request is written while it is serialized, and does not need to fit in the
+    /// buffer. The request is returned if it is kept for retrying, see [`FutureResponse::keep`].
+    pub(crate) fn request<R: NotecardRequest>(
+        &mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        cmd: R,
+    ) -> Result<Option<R>, NoteError> {
+        self.ready(delay)?;
+        let name = error::new_request_name(Some(R::NAME));
+
+        let r = self.stream(delay, false, name, cmd.timeout(), |request| {
+            ser::request_to_sink(&cmd, request)
+        });
+        let kept = self.proto.keeps(cmd.idempotent()).then_some(cmd);
+
+        match r {
+            Ok(()) => self.proto.sent(),
+            Err(e) => self.retry(delay, e, kept.as_ref())?,
+        }
+
+        Ok(kept)
+    }
+
+    /// Send a request as a command, which the Notecard does not respond to. The driver is ready
+    /// for new requests as soon as the command has been sent, and no [FutureResponse] is created.
+    /// This method is usually called through the `_cmd` API methods like [`hub::Hub::sync_cmd`].
+    pub(crate) fn command<R: NotecardRequest>(
         &mut self,
-        delay: &mut impl DelayMs<u16>,
-        cmd: T,
+        delay: &mut impl Delay<IOM::Hal>,
+        cmd: R,
     ) -> Result<(), NoteError> {
-        self.buf.clear();
-        self.buf.resize(self.buf.capacity(), 0).unwrap(); // unsafe { set_len } ?
+        self.ready(delay)?;
+        let name = error::new_request_name(Some(R::NAME));
+        self.stream(delay, true, name, None, |request| {
+            ser::request_to_sink(&cmd, request)
+        })?;
+
+        self.proto.answered();
 
-        let sz = serde_json_core::to_slice(&cmd, &mut self.buf).map_err(|_| NoteError::SerError)?;
-        self.buf.truncate(sz);
+        Ok(())
+    }
+
+    /// Send a request, e.g. one that is not wrapped by the API modules, see [`NotecardRequest`].
+    /// The returned [`FutureResponse`] must be consumed before making any new requests.
+    pub fn send<R: NotecardRequest>(
+        &mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        request: R,
+    ) -> Result<FutureResponse<'_, R::Response, IOM, BUF_SIZE, C, A, R>, NoteError> {
+        let request = self.request(delay, request)?;
+        Ok(FutureResponse::from(self).keep(request))
+    }
 
-        // Add new-line, this separator tells the Notecard that the request is done.
-        self.buf.push(b'\n').map_err(|_| NoteError::SerError)?;
-        self.send_request(delay)
+    /// Send a raw JSON request, e.g. when bridging requests from a host. The request must be a
+    /// single line ending with `\n`, otherwise [`NoteError::InvalidRequest`] is returned. Use
+    /// [`FutureResponse::wait_raw`] to get the response as it was received:
+    ///
+    /// ```ignore
+    /// let response = note
+    ///     .send_raw(&mut delay, b"{\"req\":\"card.temp\"}\n")?
+    ///     .wait_raw(&mut delay)?;
+    /// ```
+    pub fn send_raw<'r>(
+        &mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        request: &'r [u8],
+    ) -> Result<FutureResponse<'_, IgnoredAny, IOM, BUF_SIZE, C, A, &'r [u8]>, NoteError> {
+        let request = self.request_raw(delay, request)?;
+        Ok(FutureResponse::from(self).keep(request))
     }
 
     /// [card Requests](https://dev.blues.io/reference/notecard-api/card-requests/)
-    pub fn card(&mut self) -> card::Card<'_, IOM, BUF_SIZE> {
+    pub fn card(&mut self) -> card::Card<'_, IOM, BUF_SIZE, C, A> {
         card::Card::from(self)
     }
 
     /// [note Requests](https://dev.blues.io/reference/notecard-api/note-requests/)
-    pub fn note(&mut self) -> note::Note<'_, IOM, BUF_SIZE> {
+    pub fn note(&mut self) -> note::Note<'_, IOM, BUF_SIZE, C, A> {
         note::Note::from(self)
     }
 
     /// [web Requests](https://dev.blues.io/reference/notecard-api/web-requests/)
-    pub fn web(&mut self) -> web::Web<'_, IOM, BUF_SIZE> {
+    pub fn web(&mut self) -> web::Web<'_, IOM, BUF_SIZE, C, A> {
         web::Web::from(self)
     }
 
     /// [hub Requests](https://dev.blues.io/reference/notecard-api/hub-requests/)
-    pub fn hub(&mut self) -> hub::Hub<'_, IOM, BUF_SIZE> {
+    pub fn hub(&mut self) -> hub::Hub<'_, IOM, BUF_SIZE, C, A> {
         hub::Hub::from(self)
     }
 
     /// [dfu Requests](https://dev.blues.io/api-reference/notecard-api/dfu-requests/)
-    pub fn dfu(&mut self) -> dfu::DFU<'_, IOM, BUF_SIZE> {
+    pub fn dfu(&mut self) -> dfu::DFU<'_, IOM, BUF_SIZE, C, A> {
         dfu::DFU::from(self)
     }
 
     /// [NtN Requests](https://dev.blues.io/reference/notecard-api/ntn-requests/)
-    pub fn ntn(&mut self) -> ntn::NTN<'_, IOM, BUF_SIZE> {
+    pub fn ntn(&mut self) -> ntn::NTN<'_, IOM, BUF_SIZE, C, A> {
         ntn::NTN::from(self)
     }
 }
 
 /// A future response.
 ///
-/// It will not be possible to make any new requests before this has been consumed. If you drop
-/// this future before consuming the response the Notecard and driver will be left in inconsistent
-/// state. It is not safe to make new requests to the Notecard before the previous response has
-/// been read.
-#[must_use = "The response must be waited for and consumed, otherwise the notecard is left in an inconsistent state"]
+/// It will not be possible to make any new requests before this has been consumed or dropped. If
+/// you drop this future before consuming the response, e.g. when returning early on an error, the
+/// next request waits for the abandoned response and discards it before being sent.
+#[must_use = "The response must be waited for and consumed, otherwise the next request waits for it"]
 pub struct FutureResponse<
     'a,
     T: DeserializeOwned,
-    IOM: Write<SevenBitAddress> + Read<SevenBitAddress>,
+    IOM: Transport,
     const BUF_SIZE: usize,
+    C: Clock = NoClock,
+    A: Attn = NoAttn,
+    R: Resend = &'static [u8],
 > {
-    note: &'a mut Notecard<IOM, BUF_SIZE>,
+    note: &'a mut Notecard<IOM, BUF_SIZE, C, A>,
+
+    /// Timeout while waiting for response (ms).
+    timeout: u32,
+
+    /// The request, kept if it is sent again on errors, see [`RetryPolicy`].
+    request: Option<R>,
+
     _r: PhantomData<T>,
 }
 
 impl<
         'a,
         T: DeserializeOwned,
-        IOM: Write<SevenBitAddress> + Read<SevenBitAddress>,
+        IOM: Transport,
         const BUF_SIZE: usize,
-    > FutureResponse<'a, T, IOM, BUF_SIZE>
+        C: Clock,
+        A: Attn,
+        R: Resend,
+    > FutureResponse<'a, T, IOM, BUF_SIZE, C, A, R>
 {
-    fn from(note: &'a mut Notecard<IOM, BUF_SIZE>) -> FutureResponse<'a, T, IOM, BUF_SIZE> {
+    fn from(
+        note: &'a mut Notecard<IOM, BUF_SIZE, C, A>,
+    ) -> FutureResponse<'a, T, IOM, BUF_SIZE, C, A, R> {
         FutureResponse {
+            timeout: note.proto.timeout,
             note,
+            request: None,
             _r: PhantomData,
         }
     }
 
+    /// Keep the request returned by [`Notecard::request`] or [`Notecard::request_raw`] for
+    /// sending it again on errors.
+    fn keep(mut self, request: Option<R>) -> Self {
+        self.request = request;
+        self
+    }
+
+    /// Set the timeout while waiting for this response (ms), instead of the `response_timeout` of
+    /// the [`NotecardConfig`] or the longer [`timeout`](NotecardRequest::timeout) of the request.
+    pub fn timeout(mut self, timeout: u32) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Reads remaining data and returns the deserialized object if it is ready.
     pub fn poll(&mut self) -> Result<Option<T>, NoteError> {
+        let crc = self.note.proto.response_seq();
+
         match self.note.poll()? {
-            Some(body) if body.starts_with(br##"{"err":"##) => {
-                debug!(
-                    "response is error response, parsing error..: {}",
-                    core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
-                );
-                Err(
-                    serde_json_core::from_slice::<NotecardError>(body).map_or_else(
-                        |_| {
-                            error!(
-                                "failed to deserialize: {}",
-                                core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
-                            );
-                            NoteError::new_desererror(body)
-                        },
-                        |(e, _)| NoteError::from(e),
-                    ),
-                )
-            }
-            Some(body) => {
-                trace!("response is regular, parsing..");
-                Ok(Some(
-                    serde_json_core::from_slice::<T>(body)
-                        .map_err(|_| {
-                            error!(
-                                "failed to deserialize: {}",
-                                core::str::from_utf8(body).unwrap_or("[invalid utf-8]")
-                            );
-                            NoteError::new_desererror(body)
-                        })?
-                        .0,
-                ))
-            }
+            Some(body) => deserialize_response(body, crc)
+                .map(Some)
+                .map_err(|e| e.in_request(&self.note.proto.request)),
             None => Ok(None),
         }
     }
 
-    /// Wait for response and return raw bytes. These may change on next response,
-    /// so this method is probably not staying as it is.
-    pub fn wait_raw(mut self, delay: &mut impl DelayMs<u16>) -> Result<&'a [u8], NoteError> {
-        let mut waited = 0;
+    /// Wait for the response and return it as it was received, including the newline and the CRC
+    /// field if enabled. The sequence number and CRC are checked, and an error response is
+    /// returned as [`NoteError::NotecardErr`]. Idempotent requests are sent again on transient
+    /// errors, like [`wait`](Self::wait).
+    ///
+    /// The response stays in the buffer of the driver, so it is borrowed from the [`Notecard`]
+    /// until the next request is made.
+    pub fn wait_raw(self, delay: &mut impl Delay<IOM::Hal>) -> Result<&'a [u8], NoteError> {
+        let timeout = self.timeout;
+        let request = self.request;
+        let note = self.note;
+        let crc = note.proto.response_seq();
+        let mut timer = Timer::new(&mut note.clock);
+
+        loop {
+            let e = match note.poll() {
+                Ok(Some(body)) => match check_response(body, crc) {
+                    Ok(()) => break,
+                    Err(e) => e.in_request(&note.proto.request),
+                },
+                Ok(None) => {
+                    delay.delay_ms(RESPONSE_DELAY);
+                    timer.delayed(RESPONSE_DELAY.into());
+
+                    if timer.elapsed(&mut note.clock) < timeout {
+                        continue;
+                    }
+
+                    error!("response timed out (>= {}).", timeout);
+                    NoteError::TimeOut
+                }
+                Err(e) => e,
+            };
+
+            note.retry(delay, e, request.as_ref())?;
+            timer = Timer::new(&mut note.clock);
+        }
+
+        Ok(&note.proto.buf)
+    }
+
+    /// Read the response in chunks until it is complete, passing each chunk to `f` together with
+    /// the buffer of the driver. The sequence number and CRC are checked at the end.
+    fn stream(
+        &mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        mut f: impl FnMut(&mut Vec<u8, BUF_SIZE>, &[u8]) -> Result<(), NoteError>,
+    ) -> Result<(), NoteError> {
+        if !matches!(self.note.proto.state, NoteState::Poll(_)) {
+            error!("stream called when response has already been read from");
+            return Err(NoteError::WrongState);
+        }
 
-        while waited < self.note.response_timeout {
-            if self.poll()?.is_some() {
-                return self.note.take_response()
+        let mut chunk = [0u8; READ_LENGTH_MAX + 2];
+        let chunk = &mut chunk[..self.note.proto.read_length + 2];
+        let mut check = crc::Stream::new();
+        let mut timer = Timer::new(&mut self.note.clock);
+
+        self.note.proto.buf.clear();
+
+        loop {
+            let (bytes, done) = self.note.poll_chunk(chunk)?;
+            check.update(bytes);
+            f(&mut self.note.proto.buf, bytes)?;
+
+            if done {
+                break;
             }
 
             delay.delay_ms(RESPONSE_DELAY);
-            waited += RESPONSE_DELAY;
+            timer.delayed(RESPONSE_DELAY.into());
+
+            if timer.elapsed(&mut self.note.clock) >= self.timeout {
+                error!("response timed out (>= {}).", self.timeout);
+                return Err(NoteError::TimeOut);
+            }
         }
 
-        error!("response timed out (>= {}).", self.note.response_timeout);
-        Err(NoteError::TimeOut)
+        if let Some(seq) = self.note.proto.response_seq() {
+            check.check(seq)?;
+        }
+
+        Ok(())
     }
 
-    /// Wait for response and return deserialized object.
-    pub fn wait(mut self, delay: &mut impl DelayMs<u16>) -> Result<T, NoteError> {
-        let mut waited = 0;
+    /// Wait for the response and pass it to `sink` as it is read, instead of reading it into the
+    /// buffer, so that it may be larger than the buffer. An error response is returned as an
+    /// error after it has been passed to `sink`. The request is not sent again on errors.
+    pub fn wait_stream(
+        mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        sink: &mut impl Sink,
+    ) -> Result<(), NoteError> {
+        self.stream(delay, |buf, bytes| {
+            // The start of the response is kept to detect an error response.
+            let n = (BUF_SIZE - buf.len()).min(bytes.len());
+            buf.extend_from_slice(&bytes[..n]).ok();
 
-        while waited < self.note.response_timeout {
-            if let Some(r) = self.poll()? {
-                return Ok(r)
-            }
+            sink.write(bytes)
+        })?;
 
-            delay.delay_ms(RESPONSE_DELAY);
-            waited += RESPONSE_DELAY;
+        let proto = &self.note.proto;
+        if error::is_error_response(&proto.buf) {
+            Err(notecard_error(&proto.buf).in_request(&proto.request))
+        } else {
+            Ok(())
         }
+    }
 
-        error!("response timed out (>= {}).", self.note.response_timeout);
-        Err(NoteError::TimeOut)
+    /// Wait for the response and decode its base64 `payload` into `sink` as it is read, so that
+    /// the payload may be larger than the buffer, see [`stream::Base64Field`]. The rest of the
+    /// response is read into the buffer and deserialized as usual, with an empty payload. The
+    /// request is not sent again on errors.
+    pub fn wait_payload(
+        mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        sink: &mut impl Sink,
+    ) -> Result<T, NoteError> {
+        let mut payload = stream::Base64Field::new("payload", sink);
+        self.stream(delay, |buf, bytes| payload.feed(bytes, buf))
+            .map_err(|e| e.in_request(&self.note.proto.request))?;
+
+        // The CRC was checked on the full response.
+        let proto = &self.note.proto;
+        deserialize_response(&proto.buf, None).map_err(|e| e.in_request(&proto.request))
+    }
+
+    /// Wait for response and return deserialized object. Idempotent requests are sent again on
+    /// transient errors, according to the [`RetryPolicy`]. If the response is not ready yet, sleeps
+    /// until ATTN is asserted before polling again if the driver has one, see
+    /// [`Notecard::with_attn`].
+    pub fn wait(self, delay: &mut impl Delay<IOM::Hal>) -> Result<T, NoteError> {
+        self.wait_on(delay, None::<&mut NoAttn>)
+    }
+
+    /// Wait for response like [`wait`](Self::wait), but sleep until `attn` is asserted when the
+    /// response is not ready yet. The Notecard is polled as usual while ATTN stays asserted, and at
+    /// least every [`NotecardConfig::attn_poll`] ms if set.
+    pub fn wait_attn(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        attn: &mut impl Attn,
+    ) -> Result<T, NoteError> {
+        self.wait_on(delay, Some(attn))
+    }
+
+    /// Wait for the response on `attn`, or on the ATTN of the driver.
+    fn wait_on(
+        mut self,
+        delay: &mut impl Delay<IOM::Hal>,
+        mut attn: Option<&mut impl Attn>,
+    ) -> Result<T, NoteError> {
+        let mut timer = Timer::new(&mut self.note.clock);
+
+        loop {
+            let e = match self.poll() {
+                Ok(Some(r)) => return Ok(r),
+                Ok(None) if timer.elapsed(&mut self.note.clock) < self.timeout => {
+                    let remaining = timer.remaining(&mut self.note.clock, self.timeout);
+                    let remaining = remaining.min(self.note.proto.attn_poll);
+                    let waited = match attn.as_deref_mut() {
+                        Some(attn) => attn.wait(delay, remaining),
+                        None => self.note.attn.wait(delay, remaining),
+                    };
+                    timer.delayed(waited);
+
+                    // Without ATTN, or while it stays asserted, the Notecard is polled.
+                    if waited == 0 {
+                        delay.delay_ms(RESPONSE_DELAY);
+                        timer.delayed(RESPONSE_DELAY.into());
+                    }
+                    continue;
+                }
+                Ok(None) => {
+                    error!("response timed out (>= {}).", self.timeout);
+                    NoteError::TimeOut
+                }
+                Err(e) => e,
+            };
+
+            self.note.retry(delay, e, self.request.as_ref())?;
+            timer = Timer::new(&mut self.note.clock);
+        }
+    }
+
+    /// Wait for response with a timeout (ms), see [`wait`](Self::wait).
+    pub fn wait_timeout(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        timeout: u32,
+    ) -> Result<T, NoteError> {
+        self.timeout(timeout).wait(delay)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use embedded_hal_mock::eh0::delay::StdSleep;
+    use crate::emulator::fixture::{notecard, recovers, status};
+    use crate::emulator::{Emulator, Fault};
+    use embedded_hal_mock::eh0::delay::{NoopDelay, StdSleep};
     use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+    use serde_json::{json, Value};
 
-    pub fn new_mock() -> Notecard<Mock> {
+    pub fn new_mock() -> Notecard<I2c<Mock>> {
         // let exp = [ Transaction::write(0x17, vec![]) ];
         let i2c = Mock::new(&[]);
         Notecard::new(i2c)
@@ -704,12 +1570,52 @@ mod tests {
     #[test]
     fn resize_buf() {
         let c = new_mock();
-        assert_eq!(c.buf.capacity(), DEFAULT_BUF_SIZE);
+        assert_eq!(c.proto.buf.capacity(), DEFAULT_BUF_SIZE);
+
+        let c = c.resize_buf::<1024>().unwrap();
+        assert_eq!(c.proto.buf.capacity(), 1024);
+
+        c.iom.free().done();
+    }
+
+    #[test]
+    fn config_lengths() {
+        let c = NotecardConfig::default();
+        c.validate().unwrap();
+        assert_eq!(c.lengths(), (30, 240, 126));
+
+        for (chunk, segment, read) in [
+            (0, 250, 126),
+            (128, 250, 126),
+            (30, 251, 126),
+            (100, 50, 126),
+            (30, 240, 0),
+            (30, 240, 256),
+        ] {
+            let c = NotecardConfig {
+                chunk_length: chunk,
+                segment_length: segment,
+                read_length: read,
+                ..Default::default()
+            };
+            assert!(matches!(c.validate(), Err(NoteError::InvalidConfig)));
+        }
 
-        let mut c = c.resize_buf::<1024>().unwrap();
-        assert_eq!(c.buf.capacity(), 1024);
+        let c = NotecardConfig {
+            chunk_length: 200,
+            segment_length: 1000,
+            read_length: 1000,
+            ..Default::default()
+        };
+        assert_eq!(c.lengths(), (127, 127, 255));
 
-        c.i2c.done();
+        let c = NotecardConfig {
+            chunk_length: 100,
+            segment_length: 50,
+            read_length: 0,
+            ..Default::default()
+        };
+        assert_eq!(c.lengths(), (100, 100, 1));
     }
 
     #[test]
@@ -722,11 +1628,813 @@ mod tests {
             Transaction::write(0x17, expect),
         ];
         let i2c = Mock::new(&exp);
-        let mut c: Notecard<Mock> = Notecard::new(i2c);
+        let mut c: Notecard<I2c<Mock>> = Notecard::new(i2c);
         let mut delay = StdSleep::new();
         c.request_raw(&mut delay, b"{\"req\":\"card.location\"}\n")
             .unwrap();
 
-        c.i2c.done();
+        c.iom.free().done();
+    }
+
+    #[test]
+    fn serial_request() {
+        use embedded_hal_mock::eh0::serial::{Mock as SerialMock, Transaction as SerialTransaction};
+        use transport::Serial;
+
+        let exp = [
+            // handshake
+            SerialTransaction::read_error(nb::Error::WouldBlock),
+            // request
+            SerialTransaction::write_many(b"{\"req\":\"card.status\"}\n"),
+            SerialTransaction::flush(),
+            // response
+            SerialTransaction::read_error(nb::Error::WouldBlock),
+            SerialTransaction::read_many(b"{\"status\":\"{normal}\",\"storage\":8}\r\n"),
+        ];
+
+        let uart = SerialMock::new(&exp);
+        let mut c: Notecard<Serial<SerialMock<u8>>> =
+            Notecard::new_with_transport(Serial::new(uart), NotecardConfig::default());
+        let mut delay = StdSleep::new();
+
+        let status = c.card().status(&mut delay).unwrap().wait(&mut delay).unwrap();
+        assert_eq!(status.status, "{normal}");
+        assert_eq!(status.storage, 8);
+
+        c.iom.free().done();
+    }
+
+    #[test]
+    fn eh0_bus_error_kind() {
+        use embedded_hal_mock::eh0::serial::{
+            Mock as SerialMock, Transaction as SerialTransaction,
+        };
+        use embedded_hal_mock::eh0::MockError;
+        use error::{BusError, ErrorKind, NoAcknowledgeSource};
+        use std::io;
+        use transport::Serial;
+
+        struct Kinds;
+
+        impl ErrorKind<MockError> for Kinds {
+            fn kind(error: &MockError) -> BusError {
+                match error {
+                    MockError::Io(io::ErrorKind::NotConnected) => {
+                        BusError::NoAcknowledge(NoAcknowledgeSource::Address)
+                    }
+                    MockError::Io(_) => BusError::Other,
+                }
+            }
+        }
+
+        let nak = || MockError::Io(io::ErrorKind::NotConnected);
+
+        // Unclassified by default.
+        let i2c = Mock::new(&[Transaction::write(0x17, vec![0, 0]).with_error(nak())]);
+        let mut i2c = I2c::new(i2c, 0x17);
+        assert!(matches!(
+            i2c.data_query(),
+            Err(NoteError::I2cWriteError(BusError::Unknown))
+        ));
+        i2c.free().done();
+
+        let i2c = Mock::new(&[
+            Transaction::write(0x17, vec![0, 0]).with_error(nak()),
+            Transaction::write(0x17, vec![0, 0]),
+            Transaction::read(0x17, vec![0, 0]).with_error(MockError::Io(io::ErrorKind::Other)),
+        ]);
+        let mut i2c = I2c::new(i2c, 0x17).with_error_kind::<Kinds>();
+        assert!(matches!(
+            i2c.data_query(),
+            Err(NoteError::I2cWriteError(BusError::NoAcknowledge(
+                NoAcknowledgeSource::Address
+            )))
+        ));
+        assert!(matches!(
+            i2c.data_query(),
+            Err(NoteError::I2cReadError(BusError::Other))
+        ));
+        i2c.free().done();
+
+        let uart = SerialMock::new(&[SerialTransaction::read_error(nb::Error::Other(nak()))]);
+        let mut uart = Serial::new(uart).with_error_kind::<Kinds>();
+        assert!(matches!(
+            uart.data_query(),
+            Err(NoteError::SerialReadError(BusError::NoAcknowledge(
+                NoAcknowledgeSource::Address
+            )))
+        ));
+        uart.free().done();
+    }
+
+    #[test]
+    fn handshake_consumes_leftover_response() {
+        let emu = Emulator::new();
+        emu.push_response(b"{\"total\":1}\r\n");
+
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        assert_eq!(emu.pending(), 0);
+
+        let mut delay = NoopDelay::new();
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+    }
+
+    #[test]
+    fn dropped_response() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        let mut normal = |note: &mut Notecard<_>| {
+            let r = note
+                .card()
+                .status(&mut delay)
+                .unwrap()
+                .wait(&mut delay)
+                .unwrap();
+            assert_eq!(r.status, "{normal}");
+            assert!(matches!(note.proto.state, NoteState::Request));
+            assert_eq!(emu.pending(), 0);
+        };
+
+        // Dropped before the response is ready.
+        emu.inject(Fault::Slow(2));
+        let _ = note.card().version(&mut NoopDelay::new()).unwrap();
+        assert!(matches!(note.proto.state, NoteState::Poll(_)));
+        normal(&mut note);
+
+        // Dropped while reading the response.
+        let mut version = note.card().version(&mut NoopDelay::new()).unwrap();
+        assert!(version.poll().unwrap().is_none());
+        drop(version);
+        assert!(matches!(note.proto.state, NoteState::Response(_)));
+        normal(&mut note);
+
+        // Timed out, with the response arriving late.
+        emu.inject(Fault::Slow(10));
+        let r = note
+            .card()
+            .version(&mut NoopDelay::new())
+            .unwrap()
+            .timeout(100)
+            .wait(&mut NoopDelay::new());
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        normal(&mut note);
+    }
+
+    #[test]
+    fn response_overflow() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_, 128> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        let r = note.card().version(&mut delay).unwrap().wait(&mut delay);
+        assert!(matches!(r, Err(NoteError::BufOverflow)));
+        assert!(matches!(note.proto.state, NoteState::Request));
+        assert_eq!(emu.pending(), 0);
+
+        let r = note
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(r.status, "{normal}");
+        assert_eq!(emu.pending(), 0);
+    }
+
+    #[test]
+    fn endless_response_overflow() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_, 128> = notecard(&emu, |c| c.response_timeout = 100);
+        let mut delay = NoopDelay::new();
+
+        // Discarding the response is bounded by the timeout.
+        emu.inject(Fault::Endless);
+        let r = note.card().version(&mut delay).unwrap().wait(&mut delay);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        assert!(emu.pending() > 0);
+    }
+
+    #[test]
+    fn reset_before_large_request() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_, 128> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay::new();
+
+        // The reset reads the stale response to a failed request into the buffer, which is smaller
+        // than the request.
+        emu.inject(Fault::Nak(1));
+        assert!(status(&mut note).is_err());
+        emu.inject(Fault::StaleResponse([b'x'; 100].to_vec()));
+        emu.push_response(b"\r\n");
+
+        let payload = "QUJD".repeat(64);
+        note.note()
+            .add(
+                &mut delay,
+                Some("big.qo"),
+                None,
+                None::<()>,
+                Some(&payload),
+                false,
+            )
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        let requests = emu.requests();
+        assert_eq!(requests.last().unwrap()["payload"], payload.as_str());
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn send_custom_request() {
+        #[derive(serde::Serialize)]
+        struct Temp {
+            minutes: u32,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct TempResponse {
+            value: f32,
+        }
+
+        impl NotecardRequest for Temp {
+            const NAME: &'static str = "card.temp";
+            type Response = TempResponse;
+        }
+
+        let emu = Emulator::new();
+        emu.on("card.temp", |_| json!({ "value": 21.5 }));
+
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        let temp = note
+            .send(&mut delay, Temp { minutes: 5 })
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(temp.value, 21.5);
+
+        let requests = emu.requests();
+        let req = requests.last().unwrap();
+        assert_eq!(req["req"], "card.temp");
+        assert_eq!(req["minutes"], 5);
+    }
+
+    #[test]
+    fn send_raw() {
+        let emu = Emulator::new();
+        emu.on("card.temp", |_| json!({ "value": 21.5 }));
+
+        let mut note: Notecard<_> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay::new();
+
+        let response = note
+            .send_raw(&mut delay, b"{\"req\":\"card.temp\",\"minutes\":5}\n")
+            .unwrap()
+            .wait_raw(&mut delay)
+            .unwrap();
+        assert!(response.ends_with(b"\n"));
+        let response: Value = serde_json::from_slice(response).unwrap();
+        assert_eq!(response["value"], 21.5);
+        assert_eq!(emu.requests().last().unwrap()["minutes"], 5);
+
+        let r = note
+            .send_raw(
+                &mut delay,
+                b"{\"req\":\"note.get\",\"file\":\"missing.db\"}\n",
+            )
+            .unwrap()
+            .wait_raw(&mut delay);
+        assert!(matches!(r, Err(NoteError::NotecardErr(_))));
+
+        let sent = emu.requests().len();
+        for request in [
+            &b"{\"req\":\"card.temp\"}"[..],
+            b"{\"req\":\n\"card.temp\"}\n",
+        ] {
+            assert!(matches!(
+                note.send_raw(&mut delay, request),
+                Err(NoteError::InvalidRequest)
+            ));
+        }
+        assert_eq!(emu.requests().len(), sent);
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn retry_idempotent() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.retry.retries = 2);
+        let mut delay = NoopDelay::new();
+
+        // while sending
+        emu.inject(Fault::Nak(1));
+        status(&mut note).unwrap();
+        assert_eq!(emu.requests().len(), 1);
+
+        // while waiting
+        let f = note.card().status(&mut delay).unwrap();
+        emu.inject(Fault::Nak(1));
+        f.wait(&mut delay).unwrap();
+        assert_eq!(emu.requests().len(), 3);
+
+        // retries exhausted
+        let f = note.card().status(&mut delay).unwrap();
+        emu.inject(Fault::Nak(3));
+        assert!(matches!(
+            f.wait(&mut delay),
+            Err(NoteError::I2cWriteError(_))
+        ));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn retry_io_error() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.retry.retries = 2);
+
+        let mut n = 0;
+        emu.on("hub.get", move |_| {
+            n += 1;
+            match n {
+                1 => json!({ "err": "i2c: corrupted {io}" }),
+                _ => json!({ "device": "dev:1" }),
+            }
+        });
+
+        let mut delay = NoopDelay::new();
+        let hub = note
+            .hub()
+            .get(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(hub.device.as_deref(), Some("dev:1"));
+        assert_eq!(emu.requests().len(), 2);
+    }
+
+    #[test]
+    fn retry_raw() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.retry.retries = 2);
+
+        let mut n = 0;
+        emu.on("hub.get", move |_| {
+            n += 1;
+            match n {
+                1 => json!({ "err": "i2c: corrupted {io}" }),
+                _ => json!({ "device": "dev:1" }),
+            }
+        });
+
+        let mut delay = NoopDelay::new();
+        let response = note
+            .send_raw(&mut delay, b"{\"req\":\"hub.get\"}\n")
+            .unwrap()
+            .wait_raw(&mut delay)
+            .unwrap();
+        let response: Value = serde_json::from_slice(response).unwrap();
+        assert_eq!(response["device"], "dev:1");
+        assert_eq!(emu.requests().len(), 2);
+    }
+
+    #[test]
+    fn no_retry_non_idempotent() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.retry.retries = 2);
+        let mut delay = NoopDelay::new();
+
+        let f = note
+            .note()
+            .add(
+                &mut delay,
+                Some("data.qo"),
+                None,
+                Some(json!({ "temp": 1 })),
+                None,
+                false,
+            )
+            .unwrap();
+        emu.inject(Fault::Nak(1));
+        assert!(matches!(
+            f.wait(&mut delay),
+            Err(NoteError::I2cWriteError(_))
+        ));
+        assert_eq!(emu.requests().len(), 1);
+        recovers(&mut note, &emu);
+
+        // Settings are not read-only.
+        let f = note
+            .hub()
+            .set(
+                &mut delay,
+                Some("com.example:product"),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        emu.inject(Fault::Nak(1));
+        assert!(f.wait(&mut delay).is_err());
+        assert_eq!(emu.requests().len(), 3);
+        recovers(&mut note, &emu);
+
+        // Neither are notes that are deleted when read.
+        let f = note
+            .note()
+            .get::<Value>(&mut delay, "data.qi", "1", true, false)
+            .unwrap();
+        emu.inject(Fault::Nak(1));
+        assert!(f.wait(&mut delay).is_err());
+        assert_eq!(emu.requests().len(), 5);
+        recovers(&mut note, &emu);
+
+        // Only the name of the request counts, not a `req` in its body.
+        let f = note
+            .send_raw(
+                &mut delay,
+                b"{\"body\":{\"req\":\"hub.get\"},\"req\":\"note.add\",\"file\":\"data.qo\"}\n",
+            )
+            .unwrap();
+        emu.inject(Fault::Nak(1));
+        assert!(f.wait(&mut delay).is_err());
+        assert_eq!(emu.requests().len(), 7);
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn retry_long_request() {
+        #[derive(serde::Serialize)]
+        struct Temp {
+            label: std::string::String,
+        }
+
+        impl NotecardRequest for Temp {
+            const NAME: &'static str = "card.temp";
+            type Response = IgnoredAny;
+
+            fn idempotent(&self) -> bool {
+                true
+            }
+        }
+
+        let emu = Emulator::new();
+        emu.on("card.temp", |_| json!({ "value": 21.5 }));
+
+        let mut note: Notecard<_> = notecard(&emu, |c| {
+            c.retry.retries = 2;
+            c.crc = true;
+        });
+        let mut delay = NoopDelay::new();
+        let label = "x".repeat(500);
+        let temp = || Temp {
+            label: label.clone(),
+        };
+
+        // while sending
+        emu.inject(Fault::Nak(1));
+        let f = note.send(&mut delay, temp()).unwrap();
+        f.wait(&mut delay).unwrap();
+
+        // while waiting
+        let f = note.send(&mut delay, temp()).unwrap();
+        emu.inject(Fault::Nak(1));
+        f.wait(&mut delay).unwrap();
+
+        let requests = emu.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| r["label"] == label.as_str()));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn auto_tune() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.auto_tune = true);
+        assert_eq!(emu.requests()[0]["req"], "card.version");
+        assert_eq!(
+            (note.proto.chunk_length, note.proto.segment_length),
+            (125, 250)
+        );
+
+        let mut delay = NoopDelay::new();
+        let text = "x".repeat(600);
+        note.note()
+            .add(
+                &mut delay,
+                Some("data.qo"),
+                Some("t"),
+                Some(json!({ "text": text })),
+                None,
+                false,
+            )
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(emu.notes("data.qo")["t"]["body"]["text"], text.as_str());
+    }
+
+    #[test]
+    fn auto_tune_old_firmware() {
+        let emu = Emulator::new();
+        emu.respond("card.version", r#"{"body":{"ver_major":3,"ver_minor":3}}"#);
+
+        let note: Notecard<_> = notecard(&emu, |c| c.auto_tune = true);
+        assert_eq!(
+            (note.proto.chunk_length, note.proto.segment_length),
+            (30, 240)
+        );
+        assert_eq!(
+            (note.proto.chunk_delay, note.proto.segment_delay),
+            (20, 250)
+        );
+    }
+
+    #[test]
+    fn read_lengths() {
+        let status = "x".repeat(1000);
+
+        for read_length in [8, 126, 255] {
+            let emu = Emulator::new();
+            emu.respond(
+                "hub.sync.status",
+                &json!({ "status": status, "completed": 10 }).to_string(),
+            );
+
+            let mut note: Notecard<_> = notecard(&emu, |c| c.read_length = read_length);
+            let mut delay = NoopDelay::new();
+
+            let r = note
+                .hub()
+                .sync_status(&mut delay)
+                .unwrap()
+                .wait(&mut delay)
+                .unwrap();
+            assert_eq!(r.status.unwrap(), status.as_str());
+            recovers(&mut note, &emu);
+        }
+    }
+
+    #[test]
+    fn request_timeouts() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+        let mut delay = NoopDelay::new();
+
+        emu.inject(Fault::Slow(6));
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait_timeout(&mut delay, 1000)
+            .unwrap();
+
+        recovers(&mut note, &emu);
+    }
+
+    thread_local! {
+        static NOW: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+    }
+
+    /// A delay that also advances the clock by the time spent on the bus.
+    struct BusDelay;
+
+    impl Delay<Eh0> for BusDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            NOW.with(|now| now.set(now.get() + 4 * ms as u32));
+        }
+    }
+
+    #[test]
+    fn clock_timeout() {
+        // Counting delays only.
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+
+        emu.inject(Fault::Slow(2));
+        note.card()
+            .status(&mut BusDelay)
+            .unwrap()
+            .wait(&mut BusDelay)
+            .unwrap();
+
+        // Measuring the time on the bus as well.
+        let emu = Emulator::new();
+        let mut note = notecard::<DEFAULT_BUF_SIZE>(&emu, |c| c.response_timeout = 100)
+            .with_clock(|| NOW.with(|now| now.get()));
+
+        emu.inject(Fault::Slow(2));
+        let r = note
+            .card()
+            .status(&mut BusDelay)
+            .unwrap()
+            .wait(&mut BusDelay);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+    }
+
+    #[test]
+    fn request_timeout() {
+        #[derive(serde::Serialize)]
+        struct Temp {
+            #[serde(skip)]
+            timeout: Option<u32>,
+        }
+
+        impl NotecardRequest for Temp {
+            const NAME: &'static str = "card.temp";
+            type Response = IgnoredAny;
+
+            fn timeout(&self) -> Option<u32> {
+                self.timeout
+            }
+        }
+
+        let emu = Emulator::new();
+        emu.on("card.temp", |_| json!({ "value": 21.5 }));
+
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+        let mut delay = NoopDelay::new();
+        let mut temp = |note: &mut Notecard<_>, timeout| {
+            note.send(&mut delay, Temp { timeout })
+                .and_then(|r| r.wait(&mut NoopDelay::new()))
+                .map(|_| ())
+        };
+
+        // The request waits longer than the response timeout.
+        emu.inject(Fault::Slow(10));
+        temp(&mut note, Some(1000)).unwrap();
+
+        emu.inject(Fault::Slow(5));
+        assert!(matches!(temp(&mut note, None), Err(NoteError::TimeOut)));
+        recovers(&mut note, &emu);
+
+        // But never shorter.
+        emu.inject(Fault::Slow(2));
+        temp(&mut note, Some(10)).unwrap();
+
+        // A timeout set on the response takes precedence.
+        emu.inject(Fault::Slow(5));
+        let slow = Temp {
+            timeout: Some(1000),
+        };
+        let r = note
+            .send(&mut delay, slow)
+            .unwrap()
+            .timeout(100)
+            .wait(&mut delay);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn attn() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        note.card()
+            .attn(&mut delay, Some("arm"), None)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(emu.requests()[0]["mode"], "arm");
+
+        // ATTN is waited for after every poll that finds no response.
+        let mut waits = std::vec::Vec::new();
+        emu.inject(Fault::Slow(2));
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait_attn(&mut delay, &mut |timeout| {
+                waits.push(timeout);
+                0
+            })
+            .unwrap();
+        assert_eq!(waits, [5000, 4975]);
+
+        // The Notecard is polled once ATTN times out.
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait_attn(&mut delay, &mut |timeout| timeout)
+            .unwrap();
+
+        emu.inject(Fault::Slow(2));
+        let r = note
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .timeout(100)
+            .wait_attn(&mut delay, &mut |timeout| timeout);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        recovers(&mut note, &emu);
+
+        // The time waited for ATTN counts towards the timeout without a clock.
+        let mut waits = 0;
+        emu.inject(Fault::Slow(10));
+        let r = note
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .timeout(100)
+            .wait_attn(&mut delay, &mut |timeout: u32| {
+                waits += 1;
+                timeout.min(60)
+            });
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        assert_eq!(waits, 2);
+    }
+
+    #[test]
+    fn attn_pin() {
+        use embedded_hal_mock::eh0::pin::{Mock as PinMock, State, Transaction as PinTransaction};
+
+        struct Delays(std::vec::Vec<u16>);
+
+        impl Delay<Eh0> for Delays {
+            fn delay_ms(&mut self, ms: u16) {
+                self.0.push(ms);
+            }
+        }
+
+        // Read every `RESPONSE_DELAY` until asserted.
+        let pin = PinMock::new(&[
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::High),
+        ]);
+        let mut attn = AttnPin(pin);
+        let mut delays = Delays(std::vec::Vec::new());
+        assert_eq!(attn.wait(&mut delays, 1000), 50);
+        assert_eq!(delays.0, [25, 25]);
+        attn.0.done();
+
+        // Not asserted within the timeout.
+        let pin = PinMock::new(&[
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::Low),
+        ]);
+        let mut attn = AttnPin(pin);
+        let mut delays = Delays(std::vec::Vec::new());
+        assert_eq!(attn.wait(&mut delays, 30), 30);
+        assert_eq!(delays.0, [25, 5]);
+        attn.0.done();
+    }
+
+    #[test]
+    fn attn_not_asserted() {
+        let emu = Emulator::new();
+        let waits = std::cell::RefCell::new(std::vec::Vec::new());
+        let mut note =
+            notecard::<DEFAULT_BUF_SIZE>(&emu, |c| c.attn_poll = Some(100)).with_attn(|timeout| {
+                waits.borrow_mut().push(timeout);
+                timeout
+            });
+        let mut delay = NoopDelay::new();
+
+        // A response that is ready is read without waiting on ATTN, which is not re-armed.
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert!(waits.borrow().is_empty());
+        assert_eq!(emu.requests().len(), 1);
+
+        // ATTN is never asserted, the Notecard is polled every `attn_poll` ms.
+        emu.inject(Fault::Slow(2));
+        note.card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(*waits.borrow(), [100, 100]);
+        assert_eq!(emu.requests().len(), 2);
+        assert_eq!(emu.pending(), 0);
+
+        // Until the response times out.
+        waits.borrow_mut().clear();
+        emu.inject(Fault::Slow(10));
+        let r = note
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .wait_timeout(&mut delay, 250);
+        assert!(matches!(r, Err(NoteError::TimeOut)));
+        assert_eq!(*waits.borrow(), [100, 100, 50]);
     }
 }
@@ -1,12 +1,17 @@
 //! <https://dev.blues.io/reference/notecard-api/note-requests/>
 
+use core::marker::PhantomData;
+
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use super::{str_string, FutureResponse, NoteError, Notecard};
+use super::{str_string, Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport::Transport;
+
+/// The response to `note.get`, which keeps the request for retrying it.
+type GetResponse<'a, T, IOM, const BS: usize, C, A> =
+    FutureResponse<'a, res::Get<T>, IOM, BS, C, A, req::Get<T>>;
 
 pub enum TemplateFormat {
     Default,
@@ -15,12 +20,12 @@ pub enum TemplateFormat {
     Compact,
 }
 
-pub struct Note<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+pub struct Note<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> Note<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> Note<'_, IOM, BS> {
+impl<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> Note<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> Note<'_, IOM, BS, C, A> {
         Note { note }
     }
 
@@ -33,17 +38,16 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
     /// seems to work.
     pub fn add<T: Serialize + Default>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         file: Option<&str>,
         note: Option<&str>,
         body: Option<T>,
         payload: Option<&str>,
         sync: bool,
-    ) -> Result<FutureResponse<'a, res::Add, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Add, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Add::<T> {
-                req: "note.add",
                 file: str_string(file)?,
                 note: str_string(note)?,
                 body,
@@ -55,20 +59,43 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
         Ok(FutureResponse::from(self.note))
     }
 
+    /// Like [`add`](Self::add), but sent as a command without waiting for a response. Errors
+    /// adding the note are not reported.
+    pub fn add_cmd<T: Serialize + Default>(
+        self,
+        delay: &mut impl Delay<IOM::Hal>,
+        file: Option<&str>,
+        note: Option<&str>,
+        body: Option<T>,
+        payload: Option<&str>,
+        sync: bool,
+    ) -> Result<(), NoteError> {
+        self.note.command(
+            delay,
+            req::Add::<T> {
+                file: str_string(file)?,
+                note: str_string(note)?,
+                body,
+                payload,
+                sync: Some(sync),
+                ..<req::Add<T> as Default>::default()
+            },
+        )
+    }
+
     /// Updates a Note in a DB Notefile by its ID, replacing the existing body and/or payload.
     pub fn update<T: Serialize + Default>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         file: &str,
         note: &str,
         body: Option<T>,
         payload: Option<&str>,
         verify: bool,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Update::<T> {
-                req: "note.update",
                 file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
                 note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
                 body,
@@ -85,38 +112,40 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
     /// * When sending this request to Notehub, the file must be a DB Notefile (.db).
     ///
     /// .qo/.qos Notes must be read from the Notehub event table using the Notehub Event API.
+    ///
+    /// Payloads larger than the buffer can be decoded while they are read with
+    /// [`FutureResponse::wait_payload`].
     pub fn get<T: DeserializeOwned + Serialize>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         file: &str,
         note: &str,
         delete: bool,
         deleted: bool,
-    ) -> Result<FutureResponse<'a, res::Get<T>, IOM, BS>, NoteError> {
-        self.note.request(
+    ) -> Result<GetResponse<'a, T, IOM, BS, C, A>, NoteError> {
+        let request = self.note.request(
             delay,
-            req::Get {
-                req: "note.get",
+            req::Get::<T> {
                 file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
                 note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
                 delete,
                 deleted,
+                body: PhantomData,
             },
         )?;
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Deletes Notefiles from a DB Notefile by its Note ID. To delete Notes from a .qi Notefile, use note.get or note.changes with delete:true.
     pub fn delete(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         file: &str,
         note: &str,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Delete {
-                req: "note.delete",
                 file: heapless::String::try_from(file).map_err(NoteError::string_err)?,
                 note: heapless::String::try_from(note).map_err(NoteError::string_err)?,
                 verify: false,
@@ -138,14 +167,14 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
     /// for the format and values of the template.
     pub fn template<T: Serialize + Default>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         file: Option<&str>,
         body: Option<T>,
         length: Option<u32>,
         format: TemplateFormat,
         port: Option<u32>,
         delete: Option<bool>,
-    ) -> Result<FutureResponse<'a, res::Template, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Template, IOM, BS, C, A>, NoteError> {
         if let Some(port) = port && !(1..=100).contains(&port) {
                 return Err(NoteError::InvalidRequest);
         }
@@ -158,7 +187,6 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
         self.note.request(
             delay,
             req::Template::<T> {
-                req: "note.template",
                 file: str_string(file)?,
                 body,
                 length,
@@ -171,13 +199,11 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
     }
 }
 
-mod req {
+pub mod req {
     use super::*;
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Add<'a, T: Serialize + Default> {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub file: Option<heapless::String<20>>,
 
@@ -200,10 +226,13 @@ mod req {
         pub verify: Option<bool>,
     }
 
+    impl<T: Serialize + Default> NotecardRequest for Add<'_, T> {
+        const NAME: &'static str = "note.add";
+        type Response = res::Add;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Update<'a, T: Serialize + Default> {
-        pub req: &'static str,
-
         pub file: heapless::String<20>,
         pub note: heapless::String<20>,
 
@@ -216,30 +245,47 @@ mod req {
         pub verify: bool,
     }
 
+    impl<T: Serialize + Default> NotecardRequest for Update<'_, T> {
+        const NAME: &'static str = "note.update";
+        type Response = res::Empty;
+    }
+
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Delete {
-        pub req: &'static str,
-
         pub file: heapless::String<20>,
         pub note: heapless::String<20>,
         pub verify: bool,
     }
 
-    #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
-    pub struct Get {
-        pub req: &'static str,
+    impl NotecardRequest for Delete {
+        const NAME: &'static str = "note.delete";
+        type Response = res::Empty;
+    }
 
+    #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
+    pub struct Get<T> {
         pub file: heapless::String<20>,
         pub note: heapless::String<20>,
 
         pub delete: bool,
         pub deleted: bool,
+
+        /// Type of the body of the note in the response.
+        #[serde(skip)]
+        pub body: PhantomData<T>,
+    }
+
+    impl<T: DeserializeOwned + Serialize> NotecardRequest for Get<T> {
+        const NAME: &'static str = "note.get";
+        type Response = res::Get<T>;
+
+        fn idempotent(&self) -> bool {
+            !self.delete
+        }
     }
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Template<T: Serialize + Default> {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub file: Option<heapless::String<20>>,
 
@@ -258,6 +304,11 @@ mod req {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub delete: Option<bool>,
     }
+
+    impl<T: Serialize + Default> NotecardRequest for Template<T> {
+        const NAME: &'static str = "note.template";
+        type Response = res::Template;
+    }
 }
 
 pub mod res {
@@ -298,7 +349,11 @@ pub mod res {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::emulator::fixture::notecard;
+    use crate::emulator::Emulator;
     use crate::DEFAULT_BUF_SIZE as BUF_SIZE;
+    use crate::{NoteState, Notecard};
+    use embedded_hal_mock::eh0::delay::NoopDelay;
 
     #[test]
     fn add_with_template() {
@@ -355,7 +410,6 @@ mod tests {
         let b64 = core::str::from_utf8(&b64).unwrap();
 
         let add = req::Add::<AxlPacket> {
-            req: "note.add",
             file: Some("axl.qo".try_into().unwrap()),
             note: Some("?".try_into().unwrap()),
             body: Some(p),
@@ -368,4 +422,92 @@ mod tests {
 
         println!("cmd size: {}", cmd.len());
     }
+
+    #[test]
+    fn add_and_get_note() {
+        #[derive(serde::Serialize, serde::Deserialize, Default, Debug, PartialEq)]
+        struct Body {
+            temp: u32,
+        }
+
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        note.note()
+            .add(
+                &mut delay,
+                Some("sensors.db"),
+                Some("t"),
+                Some(Body { temp: 21 }),
+                None,
+                false,
+            )
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        assert_eq!(emu.notes("sensors.db")["t"]["body"]["temp"], 21);
+
+        let got = note
+            .note()
+            .get::<Body>(&mut delay, "sensors.db", "t", false, false)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(got.body, Some(Body { temp: 21 }));
+
+        let r = note
+            .note()
+            .get::<Body>(&mut delay, "sensors.db", "missing", false, false)
+            .unwrap()
+            .wait(&mut delay);
+        let e = r.unwrap_err();
+        assert_eq!(e.request(), Some("note.get"));
+        assert_eq!(e.to_string(), "note.get: note not found {note-noexist}");
+        let NoteError::NotecardErr(e) = e else {
+            panic!("expected error response");
+        };
+        assert!(e.tags().contains(crate::error::ErrorTag::NoteNoexist));
+        assert_eq!(e.message(), "note not found");
+        assert!(matches!(note.proto.state, NoteState::Request));
+    }
+
+    #[test]
+    fn add_rejected() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        emu.respond(
+            "note.add",
+            r#"{"err":"adding notes to a non-uplinked port is not allowed"}"#,
+        );
+        let e = note
+            .note()
+            .add(&mut delay, Some("data.qo"), None, Some(1), None, false)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap_err();
+        assert!(matches!(e, NoteError::NonPortNoteInPackageMode(_)));
+        assert_eq!(e.request(), Some("note.add"));
+        assert_eq!(
+            e.to_string(),
+            "note.add: adding notes to a non-uplinked port is not allowed"
+        );
+
+        emu.respond(
+            "note.add",
+            r#"{"err":"firmware update in progress {dfu-in-progress}"}"#,
+        );
+        let e = note
+            .note()
+            .add(&mut delay, Some("data.qo"), None, Some(1), None, false)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap_err();
+        assert!(matches!(e, NoteError::DFUInProgress(_)));
+        assert_eq!(e.request(), Some("note.add"));
+        assert!(e.tags().contains(crate::error::ErrorTag::DfuInProgress));
+    }
 }
@@ -2,14 +2,13 @@
 
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{Deserialize, Serialize};
 
-use super::{FutureResponse, NoteError, Notecard};
+use super::{Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport::Transport;
 
-pub struct NTN<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+pub struct NTN<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
 #[derive(Clone, Copy, defmt::Format)]
@@ -21,16 +20,16 @@ pub enum NtnSetGps {
     Starnote,
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> NTN<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> NTN<'_, IOM, BS> {
+impl<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> NTN<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> NTN<'_, IOM, BS, C, A> {
         NTN { note }
     }
 
     /// Once a Notecard is connected to a Starnote device, the presence of a physical Starnote is stored in a permanent configuration that is not affected by a card.restore request. This request clears this configuration and allows you to return to testing NTN mode over cellular or Wi-Fi.
     pub fn reset<const PS: usize>(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS>, NoteError> {
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Empty, IOM, BS, C, A>, NoteError> {
         self.note.request_raw(delay, b"{\"req\":\"ntn.reset\"}\n")?;
         Ok(FutureResponse::from(self.note))
     }
@@ -39,24 +38,24 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> N
     /// firmware updates.
     pub fn status(
         self,
-        delay: &mut impl DelayMs<u16>,
-    ) -> Result<FutureResponse<'a, res::Status, IOM, BS>, NoteError> {
-        self.note
+        delay: &mut impl Delay<IOM::Hal>,
+    ) -> Result<FutureResponse<'a, res::Status, IOM, BS, C, A>, NoteError> {
+        let request = self
+            .note
             .request_raw(delay, b"{\"req\":\"ntn.status\"}\n")?;
 
-        Ok(FutureResponse::from(self.note))
+        Ok(FutureResponse::from(self.note).keep(request))
     }
 
     /// Determines whether a Notecard should override a paired Starnote's GPS/GNSS location with its own GPS/GNSS location. The paired Starnote uses its own GPS/GNSS location by default.
     pub fn gps(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         gps: Option<NtnSetGps>,
-    ) -> Result<FutureResponse<'a, res::Gps, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Gps, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Gps {
-                req: "ntn.gps",
                 on: gps.map(|g| matches!(g, NtnSetGps::Notecard)),
                 off: gps.map(|g| matches!(g, NtnSetGps::Starnote)),
             },
@@ -70,14 +69,17 @@ pub mod req {
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Gps {
-        pub req: &'static str,
-
         #[serde(skip_serializing_if = "Option::is_none")]
         pub on: Option<bool>,
 
         #[serde(skip_serializing_if = "Option::is_none")]
         pub off: Option<bool>,
     }
+
+    impl NotecardRequest for Gps {
+        const NAME: &'static str = "ntn.gps";
+        type Response = res::Gps;
+    }
 }
 
 pub mod res {
@@ -0,0 +1,417 @@
+//! The state of the request and response protocol, without any I/O. The blocking
+//! [`Notecard`](crate::Notecard) and the async [`AsyncNotecard`](crate::asynch::AsyncNotecard)
+//! keep a [`Protocol`], do the transfers and delays it asks for on their bus, and report the
+//! results back to it.
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use heapless::Vec;
+use serde::de::DeserializeOwned;
+
+use crate::error::{is_error_response, RequestName};
+use crate::{
+    FirmwareVersion, NoteError, NoteState, NotecardConfig, RetryPolicy, SEGMENT_LENGTH_MAX,
+    TUNED_CHUNK_DELAY, TUNED_CHUNK_LENGTH, TUNED_SEGMENT_DELAY,
+};
+
+/// The next step while receiving a response, see [`Protocol::step`].
+pub(crate) enum Step {
+    /// Query the Notecard for available bytes, and report them with [`Protocol::queried`].
+    Query,
+
+    /// Read the next part of the response into [`Protocol::read_buf`], value is remaining bytes.
+    Read(usize),
+
+    /// The response does not fit in the buffer, read and discard the next part with
+    /// [`Protocol::discard_buf`], value is remaining bytes.
+    Discard(usize),
+
+    /// The full response is in the buffer, see [`Protocol::take_response`].
+    Ready,
+}
+
+/// State and configuration of a driver.
+pub(crate) struct Protocol<const BUF_SIZE: usize> {
+    pub(crate) state: NoteState,
+
+    /// The receive buffer. Must be large enough to hold the largest response that will be
+    /// received.
+    pub(crate) buf: Vec<u8, BUF_SIZE>,
+
+    pub(crate) response_timeout: u32,
+
+    /// Longest wait on ATTN between polls (ms), see [`NotecardConfig::attn_poll`].
+    pub(crate) attn_poll: u32,
+
+    /// Timeout while waiting for the response to the last request (ms), `response_timeout` unless
+    /// the request takes longer, see [`NotecardRequest::timeout`](crate::NotecardRequest::timeout).
+    pub(crate) timeout: u32,
+
+    pub(crate) chunk_delay: u16,
+    pub(crate) segment_delay: u16,
+
+    pub(crate) chunk_length: usize,
+    pub(crate) segment_length: usize,
+    pub(crate) read_length: usize,
+    pub(crate) auto_tune: bool,
+
+    /// Add sequence number and CRC to requests.
+    pub(crate) crc: bool,
+
+    /// Sequence number of the last request.
+    pub(crate) seq: u16,
+
+    pub(crate) retry_policy: RetryPolicy,
+
+    /// Number of times the last request has been retried.
+    pub(crate) retried: u8,
+
+    /// Name of the last request, attached to its errors.
+    pub(crate) request: RequestName,
+
+    /// The last request has not been answered, e.g. after a timeout. A late response may then be
+    /// waiting when the next request is made.
+    pub(crate) unanswered: bool,
+
+    /// The response does not fit in the buffer, and is being read and discarded.
+    pub(crate) discarding: bool,
+}
+
+impl<const BUF_SIZE: usize> Protocol<BUF_SIZE> {
+    pub(crate) fn new(c: &NotecardConfig) -> Protocol<BUF_SIZE> {
+        let (chunk_length, segment_length, read_length) = c.lengths();
+
+        Protocol {
+            state: NoteState::Handshake,
+            buf: Vec::new(),
+
+            response_timeout: c.response_timeout,
+            timeout: c.response_timeout,
+            attn_poll: c.attn_poll.unwrap_or(u32::MAX),
+            chunk_delay: c.chunk_delay,
+            segment_delay: c.segment_delay,
+            chunk_length,
+            segment_length,
+            read_length,
+            auto_tune: c.auto_tune,
+            crc: c.crc,
+            seq: 0,
+            retry_policy: c.retry,
+            retried: 0,
+            request: RequestName::new(),
+            unanswered: false,
+            discarding: false,
+        }
+    }
+
+    /// Move the state to a buffer of another size.
+    pub(crate) fn resize<const B: usize>(self) -> Result<Protocol<B>, NoteError> {
+        match Vec::<_, B>::from_slice(&self.buf) {
+            Ok(buf) => Ok(Protocol { buf, ..self }),
+            Err(_) => Err(NoteError::BufOverflow),
+        }
+    }
+
+    /// Use larger chunks and shorter delays if the firmware supports them, see
+    /// [`Notecard::tune`](crate::Notecard::tune).
+    pub(crate) fn tune(&mut self, version: &FirmwareVersion) {
+        if version.tunable() {
+            info!("note: firmware >= 3.4, tuning chunks and delays.");
+            self.chunk_length = TUNED_CHUNK_LENGTH;
+            self.segment_length = (SEGMENT_LENGTH_MAX / TUNED_CHUNK_LENGTH) * TUNED_CHUNK_LENGTH;
+            self.chunk_delay = TUNED_CHUNK_DELAY;
+            self.segment_delay = TUNED_SEGMENT_DELAY;
+        }
+    }
+
+    /// The spacing of the chunks of a request.
+    pub(crate) fn pacing(&self) -> Pacing {
+        Pacing {
+            chunk_delay: self.chunk_delay,
+            segment_delay: self.segment_delay,
+            segment_length: self.segment_length,
+            segment: 0,
+        }
+    }
+
+    /// Check that the Notecard may be queried for available bytes.
+    pub(crate) fn query(&self) -> Result<(), NoteError> {
+        trace!("note: data_query: {:?}", self.state);
+        if matches!(self.state, NoteState::Response(_)) {
+            error!("note: data_query called while reading response.");
+            Err(NoteError::WrongState)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The Notecard has `available` bytes ready, a response is read when there are any.
+    pub(crate) fn queried(&mut self, available: usize) -> usize {
+        if available > 0 {
+            self.buf.clear();
+            self.state = NoteState::Response(available);
+        }
+
+        available
+    }
+
+    /// The next step while receiving the response into the buffer.
+    pub(crate) fn step(&self) -> Result<Step, NoteError> {
+        trace!("note: poll: {:?}", self.state);
+        match self.state {
+            NoteState::Poll(_) => Ok(Step::Query),
+            NoteState::Response(avail) => {
+                // Room for the two byte header of the I2C transport is needed.
+                if self.discarding || BUF_SIZE - self.buf.len() <= 2 {
+                    Ok(Step::Discard(avail))
+                } else {
+                    Ok(Step::Read(avail))
+                }
+            }
+            NoteState::ResponseReady => Ok(Step::Ready),
+            _ => {
+                error!("poll called when not receiving response");
+                Err(NoteError::WrongState)
+            }
+        }
+    }
+
+    /// The next step while receiving the response in chunks, see [`Step::Read`].
+    pub(crate) fn chunk_step(&self) -> Result<Step, NoteError> {
+        trace!("note: poll_chunk: {:?}", self.state);
+        match self.state {
+            NoteState::Poll(_) => Ok(Step::Query),
+            NoteState::Response(avail) => Ok(Step::Read(avail)),
+            _ => {
+                error!("poll_chunk called when not receiving response");
+                Err(NoteError::WrongState)
+            }
+        }
+    }
+
+    /// Room in the tail of the buffer for the next read, and the length of the buffer before it.
+    /// The read is reported with [`read`](Self::read).
+    pub(crate) fn read_buf(&mut self) -> Result<(usize, &mut [u8]), NoteError> {
+        let len = self.buf.len();
+        let room = (self.read_length + 2).min(BUF_SIZE - len);
+
+        self.buf
+            .resize(len + room, 0)
+            .map_err(|_| NoteError::BufOverflow)?;
+
+        Ok((len, &mut self.buf[len..]))
+    }
+
+    /// A read into [`read_buf`](Self::read_buf) has finished, returns the remaining bytes.
+    pub(crate) fn read(
+        &mut self,
+        len: usize,
+        r: Result<(usize, usize), NoteError>,
+    ) -> Result<usize, NoteError> {
+        let (sz, available) = match r {
+            Ok(r) => r,
+            Err(e) => {
+                self.buf.truncate(len);
+                return Err(e);
+            }
+        };
+
+        self.buf.truncate(len + sz);
+
+        if available > 0 {
+            self.state = NoteState::Response(available);
+        } else {
+            self.state = NoteState::ResponseReady;
+        }
+
+        Ok(available)
+    }
+
+    /// A read into a chunk outside the buffer has finished, `available` bytes are remaining.
+    pub(crate) fn chunk_read(&mut self, available: usize) {
+        if available > 0 {
+            self.state = NoteState::Response(available);
+        } else {
+            self.answered();
+        }
+    }
+
+    /// Room for the next read of a response that does not fit in the buffer. The read is reported
+    /// with [`discarded`](Self::discarded).
+    pub(crate) fn discard_buf(&mut self) -> Result<&mut [u8], NoteError> {
+        if !self.discarding {
+            error!(
+                "read: response does not fit in buffer ({} bytes), discarding it.",
+                BUF_SIZE
+            );
+            self.discarding = true;
+        }
+
+        self.buf.clear();
+        self.buf
+            .resize((self.read_length + 2).min(BUF_SIZE), 0)
+            .map_err(|_| NoteError::BufOverflow)?;
+
+        Ok(&mut self.buf)
+    }
+
+    /// A read into [`discard_buf`](Self::discard_buf) has finished, returns the remaining bytes.
+    /// The rest is discarded by the next polls, or drained by the next request.
+    pub(crate) fn discarded(
+        &mut self,
+        r: Result<(usize, usize), NoteError>,
+    ) -> Result<usize, NoteError> {
+        self.buf.clear();
+        let available = r?.1;
+
+        if available > 0 {
+            self.state = NoteState::Response(available);
+        } else {
+            self.answered();
+        }
+
+        Ok(available)
+    }
+
+    /// Take the response from the buffer. Once this function has been called, the state is reset
+    /// and it is no longer safe to read the buffer.
+    ///
+    /// Safety:
+    ///
+    /// This function returns an immutable reference to the buffer, but new requests require a
+    /// mutable reference to the driver. This is not granted before the immutable reference is
+    /// released.
+    pub(crate) fn take_response(&mut self) -> Result<&[u8], NoteError> {
+        if matches!(self.state, NoteState::ResponseReady) {
+            debug!("response read, deserializing.");
+            self.answered();
+
+            Ok(&self.buf)
+        } else {
+            error!("take response called when response not ready");
+            Err(NoteError::WrongState)
+        }
+    }
+
+    /// Partial data of the last response if it was an error response, the buffer holds it until
+    /// the next request.
+    pub(crate) fn error_data<D: DeserializeOwned>(&self) -> Option<D> {
+        if !matches!(self.state, NoteState::Request) || !is_error_response(&self.buf) {
+            return None;
+        }
+
+        serde_json_core::from_slice::<D>(&self.buf)
+            .ok()
+            .map(|(d, _)| d)
+    }
+
+    /// Start a reset, the handshake must follow.
+    pub(crate) fn reset(&mut self) {
+        warn!("resetting: consuming any left-over response and perform a new handshake.");
+
+        self.buf.clear(); // clear in case data_query() is 0.
+        self.state = NoteState::Handshake;
+        self.discarding = false;
+    }
+
+    /// Whether the response to the previous request was abandoned, and must be drained before a
+    /// new request, see [`NoteState`].
+    pub(crate) fn abandoned(&self) -> bool {
+        match self.state {
+            // The handshake is done when sending the request.
+            NoteState::Request | NoteState::Handshake => false,
+            NoteState::Poll(_) | NoteState::Response(_) | NoteState::ResponseReady => {
+                warn!("note: previous response was abandoned, draining it.");
+                true
+            }
+        }
+    }
+
+    /// Start a new request, waiting for at least `timeout` (ms) for its response. Returns its
+    /// sequence number if CRCs are used.
+    pub(crate) fn begin(&mut self, request: RequestName, timeout: Option<u32>) -> Option<u16> {
+        self.request = request;
+        self.timeout = timeout.map_or(self.response_timeout, |t| t.max(self.response_timeout));
+        self.unanswered = true;
+        self.retried = 0;
+
+        self.crc.then(|| {
+            self.seq = self.seq.wrapping_add(1);
+            self.seq
+        })
+    }
+
+    /// The sequence number the response must have, if CRCs are used.
+    pub(crate) fn response_seq(&self) -> Option<u16> {
+        self.crc.then_some(self.seq)
+    }
+
+    /// Whether a request is kept for being sent again, which is only done for `idempotent`
+    /// requests, see [`RetryPolicy`].
+    pub(crate) fn keeps(&self, idempotent: bool) -> bool {
+        idempotent && self.retry_policy.retries > 0
+    }
+
+    /// Whether the last request, which was kept, should be sent again after the error `e`,
+    /// according to the retry policy.
+    pub(crate) fn retrying(&mut self, e: &NoteError) -> bool {
+        if e.is_transient() && self.retried < self.retry_policy.retries {
+            self.retried += 1;
+            warn!(
+                "note: retrying request ({} / {}) after: {:?}",
+                self.retried, self.retry_policy.retries, e
+            );
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The response to the last request has been read, or none is expected, and a new request can
+    /// be made.
+    pub(crate) fn answered(&mut self) {
+        self.state = NoteState::Request;
+        self.unanswered = false;
+        self.discarding = false;
+    }
+
+    /// The request has been sent, and the response is waited for.
+    pub(crate) fn sent(&mut self) {
+        self.state = NoteState::Poll(0);
+    }
+}
+
+/// Spaces the chunks of a request, `chunk_delay` after every chunk and `segment_delay` after
+/// every `segment_length` bytes.
+pub(crate) struct Pacing {
+    chunk_delay: u16,
+    segment_delay: u16,
+    segment_length: usize,
+
+    /// Bytes written in the current segment.
+    segment: usize,
+}
+
+impl Pacing {
+    /// A chunk of `len` bytes has been written, returns the delay before the next (ms).
+    pub(crate) fn chunk(&mut self, len: usize) -> u16 {
+        self.segment += len;
+
+        if self.segment >= self.segment_length {
+            self.segment = 0;
+            self.chunk_delay.saturating_add(self.segment_delay)
+        } else {
+            self.chunk_delay
+        }
+    }
+
+    /// The request has been written, returns the delay for the last segment (ms).
+    pub(crate) fn finish(&self) -> u16 {
+        if self.segment > 0 {
+            self.segment_delay
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,602 @@
+//! Streaming JSON serializer, writing the serialized request to a [`Sink`] as it is produced so
+//! that requests do not need to fit in a buffer.
+//!
+//! The output is the same as that of `serde_json_core::to_slice`, which is used elsewhere: no
+//! whitespace, `None` as `null`, floats formatted with `ryu` and strings escaped the same way.
+
+use core::fmt;
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use serde::ser::{self, Serialize};
+
+use crate::stream::Sink;
+use crate::{NoteError, NotecardRequest};
+
+/// Serialize `value` into `sink`.
+pub(crate) fn to_sink<T: Serialize + ?Sized>(
+    value: &T,
+    sink: &mut impl Sink,
+) -> Result<(), NoteError> {
+    value
+        .serialize(&mut Serializer { sink })
+        .map_err(|Error(e)| e)
+}
+
+/// Serialize `request` into `sink`, with its name added as the first field `req` of the object.
+pub(crate) fn request_to_sink<R: NotecardRequest>(
+    request: &R,
+    sink: &mut impl Sink,
+) -> Result<(), NoteError> {
+    let mut named = Named {
+        sink,
+        name: R::NAME,
+        state: NameState::Start,
+    };
+    to_sink(request, &mut named)?;
+
+    match named.state {
+        NameState::Body => Ok(()),
+        _ => Err(NoteError::SerError),
+    }
+}
+
+enum NameState {
+    /// Before the opening brace.
+    Start,
+
+    /// After the opening brace, the field needs a comma if the object is not empty.
+    Open,
+
+    /// In the rest of the object.
+    Body,
+}
+
+/// Serialize `request` like [`request_to_sink`] into a string, for tests.
+#[cfg(test)]
+pub(crate) fn request_string<R: NotecardRequest, const N: usize>(
+    request: &R,
+) -> Result<heapless::String<N>, NoteError> {
+    let mut buf = heapless::Vec::<u8, N>::new();
+    request_to_sink(request, &mut buf)?;
+
+    Ok(core::str::from_utf8(&buf).unwrap().into())
+}
+
+/// Adds the `req` field to the start of a serialized object.
+struct Named<'a, S: Sink> {
+    sink: &'a mut S,
+    name: &'static str,
+    state: NameState,
+}
+
+impl<S: Sink> Sink for Named<'_, S> {
+    fn write(&mut self, mut bytes: &[u8]) -> Result<(), NoteError> {
+        if let NameState::Start = self.state
+            && let Some(&b) = bytes.first()
+        {
+            if b != b'{' {
+                error!("ser: request is not serialized as an object.");
+                return Err(NoteError::SerError);
+            }
+
+            self.sink.write(br#"{"req":"#)?;
+            to_sink(self.name, self.sink)?;
+            self.state = NameState::Open;
+            bytes = &bytes[1..];
+        }
+
+        if let NameState::Open = self.state
+            && let Some(&b) = bytes.first()
+        {
+            if b != b'}' {
+                self.sink.write(b",")?;
+            }
+            self.state = NameState::Body;
+        }
+
+        self.sink.write(bytes)
+    }
+}
+
+/// Error while serializing, either from the sink or [`NoteError::SerError`] for values that can
+/// not be serialized.
+#[derive(Debug)]
+pub(crate) struct Error(NoteError);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "serialization error: {:?}", self.0)
+    }
+}
+
+impl ser::StdError for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error(NoteError::SerError)
+    }
+}
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+pub(crate) struct Serializer<'a, S: Sink> {
+    sink: &'a mut S,
+}
+
+impl<S: Sink> Serializer<'_, S> {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.sink.write(bytes).map_err(Error)
+    }
+
+    /// Write an unsigned integer with its digits formatted into `buf`.
+    fn write_digits(&mut self, mut v: u64, negative: bool) -> Result<()> {
+        let mut buf = [0u8; 20];
+        let mut i = buf.len();
+
+        loop {
+            i -= 1;
+            buf[i] = (v % 10) as u8 + b'0';
+            v /= 10;
+
+            if v == 0 {
+                break;
+            }
+        }
+
+        if negative {
+            self.write(b"-")?;
+        }
+
+        self.write(&buf[i..])
+    }
+
+    fn write_signed(&mut self, v: i64) -> Result<()> {
+        self.write_digits(v.unsigned_abs(), v < 0)
+    }
+}
+
+/// Upper-case hex for value in 0..16, encoded as ASCII bytes
+fn hex(c: u8) -> u8 {
+    b"0123456789ABCDEF"[c as usize & 0xf]
+}
+
+impl<'a, 'b: 'a, S: Sink> ser::Serializer for &'a mut Serializer<'b, S> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, 'b, S>;
+    type SerializeTuple = Compound<'a, 'b, S>;
+    type SerializeTupleStruct = Compound<'a, 'b, S>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = Compound<'a, 'b, S>;
+    type SerializeStruct = Compound<'a, 'b, S>;
+    type SerializeStructVariant = Compound<'a, 'b, S>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write(if v { b"true" } else { b"false" })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.write_signed(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.write_signed(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.write_signed(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.write_signed(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.write_digits(v.into(), false)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.write_digits(v.into(), false)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.write_digits(v.into(), false)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.write_digits(v, false)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        if v.is_finite() {
+            self.write(ryu::Buffer::new().format(v).as_bytes())
+        } else {
+            self.serialize_none()
+        }
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        if v.is_finite() {
+            self.write(ryu::Buffer::new().format(v).as_bytes())
+        } else {
+            self.serialize_none()
+        }
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0u8; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write(b"\"")?;
+
+        // Runs of characters that need no escaping are written at once.
+        let bytes = v.as_bytes();
+        let mut start = 0;
+
+        for (i, &b) in bytes.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'\\' => b"\\\\",
+                b'"' => b"\\\"",
+                0x08 => b"\\b",
+                b'\t' => b"\\t",
+                b'\n' => b"\\n",
+                0x0c => b"\\f",
+                b'\r' => b"\\r",
+                0x00..=0x1f => &[b'\\', b'u', b'0', b'0', hex(b >> 4), hex(b)],
+                _ => continue,
+            };
+
+            self.write(&bytes[start..i])?;
+            self.write(escaped)?;
+            start = i + 1;
+        }
+
+        self.write(&bytes[start..])?;
+        self.write(b"\"")
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.write(b"null")
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.serialize_none()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let mut s = self.serialize_struct("", 1)?;
+        ser::SerializeStruct::serialize_field(&mut s, variant, value)?;
+        ser::SerializeStruct::end(s)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.write(b"[")?;
+        Ok(Compound::new(self, b"]"))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error(NoteError::SerError))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.write(b"{")?;
+        Ok(Compound::new(self, b"}"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.write(b"{")?;
+        Ok(Compound::new(self, b"}"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.write(b"{\"")?;
+        self.write(variant.as_bytes())?;
+        self.write(b"\":{")?;
+        Ok(Compound::new(self, b"}}"))
+    }
+
+    fn collect_str<T: ?Sized + fmt::Display>(self, _value: &T) -> Result<()> {
+        Err(Error(NoteError::SerError))
+    }
+}
+
+/// Sequences, maps and structs, separating elements with commas and closed with `end`.
+pub(crate) struct Compound<'a, 'b, S: Sink> {
+    ser: &'a mut Serializer<'b, S>,
+    first: bool,
+    end: &'static [u8],
+}
+
+impl<'a, 'b, S: Sink> Compound<'a, 'b, S> {
+    fn new(ser: &'a mut Serializer<'b, S>, end: &'static [u8]) -> Self {
+        Compound {
+            ser,
+            first: true,
+            end,
+        }
+    }
+
+    fn separate(&mut self) -> Result<()> {
+        if !self.first {
+            self.ser.write(b",")?;
+        }
+        self.first = false;
+        Ok(())
+    }
+
+    fn field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.separate()?;
+        self.ser.write(b"\"")?;
+        self.ser.write(key.as_bytes())?;
+        self.ser.write(b"\":")?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self) -> Result<()> {
+        self.ser.write(self.end)
+    }
+}
+
+impl<S: Sink> ser::SerializeSeq for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.separate()?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<S: Sink> ser::SerializeTuple for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<S: Sink> ser::SerializeTupleStruct for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<S: Sink> ser::SerializeMap for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.separate()?;
+        key.serialize(&mut *self.ser)?;
+        self.ser.write(b":")
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<S: Sink> ser::SerializeStruct for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<S: Sink> ser::SerializeStructVariant for Compound<'_, '_, S> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::Vec;
+    use serde::Serialize;
+
+    /// Serialize with both serializers and check that the output is the same.
+    fn same<T: Serialize>(value: &T) {
+        let mut streamed: Vec<u8, 512> = Vec::new();
+        to_sink(value, &mut streamed).unwrap();
+
+        let buffered: Vec<u8, 512> = serde_json_core::to_vec(value).unwrap();
+        assert_eq!(
+            core::str::from_utf8(&streamed).unwrap(),
+            core::str::from_utf8(&buffered).unwrap()
+        );
+    }
+
+    #[derive(Serialize)]
+    enum Kind {
+        #[serde(rename = "unit")]
+        Unit,
+        Struct {
+            a: u8,
+        },
+    }
+
+    #[derive(Serialize)]
+    struct Request<'a> {
+        req: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<&'a str>,
+        sync: bool,
+        none: Option<u32>,
+        numbers: [i64; 4],
+        floats: (f32, f64, f64),
+        kind: Kind,
+        inner: Kind,
+        text: &'a str,
+    }
+
+    #[test]
+    fn same_as_serde_json_core() {
+        same(&Request {
+            req: "note.add",
+            file: Some("data.qo"),
+            sync: true,
+            none: None,
+            numbers: [0, -1, i64::MIN, i64::MAX],
+            floats: (0.5, -1.25e-7, f64::NAN),
+            kind: Kind::Unit,
+            inner: Kind::Struct { a: 255 },
+            text: "quote \" backslash \\ newline \n tab \t bell \u{7} ä 💣",
+        });
+
+        same(&[u64::MAX, 0, 10]);
+        same(&heapless::String::<8>::from("x"));
+        same(&());
+
+        let mut map = heapless::FnvIndexMap::<&str, u16, 4>::new();
+        map.insert("a", 1).unwrap();
+        map.insert("b", 2).unwrap();
+        same(&map);
+    }
+
+    #[derive(Serialize)]
+    struct Named<T> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<T>,
+    }
+
+    impl NotecardRequest for Named<&str> {
+        const NAME: &'static str = "file.stats";
+        type Response = ();
+    }
+
+    impl NotecardRequest for [u8; 1] {
+        const NAME: &'static str = "array";
+        type Response = ();
+    }
+
+    fn named<R: NotecardRequest>(request: &R) -> Result<Vec<u8, 64>, NoteError> {
+        let mut buf = Vec::new();
+        request_to_sink(request, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn request_name() {
+        assert_eq!(
+            &named(&Named { file: Some("a.qo") }).unwrap()[..],
+            br#"{"req":"file.stats","file":"a.qo"}"#
+        );
+        assert_eq!(
+            &named(&Named::<&str> { file: None }).unwrap()[..],
+            br#"{"req":"file.stats"}"#
+        );
+        assert!(matches!(named(&[1u8]), Err(NoteError::SerError)));
+    }
+
+    #[test]
+    fn sink_overflow() {
+        let mut small: Vec<u8, 8> = Vec::new();
+        assert!(matches!(
+            to_sink(&"longer than eight", &mut small),
+            Err(NoteError::BufOverflow)
+        ));
+    }
+}
@@ -0,0 +1,435 @@
+//! Streaming responses that are larger than the buffer of the driver, such as `dfu.get` and
+//! `note.get` payloads.
+//!
+//! [`FutureResponse::wait_stream`](crate::FutureResponse::wait_stream) passes the response to a
+//! [`Sink`] as it is read, chunk by chunk. [`Base64Field`] decodes a base64 field of the response
+//! while it is streamed, which is done for the `payload` by
+//! [`FutureResponse::wait_payload`](crate::FutureResponse::wait_payload):
+//!
+//! ```ignore
+//! let mut offset = 0;
+//! let mut flash = |bytes: &[u8]| {
+//!     write_to_flash(offset, bytes);
+//!     offset += bytes.len();
+//!     Ok(())
+//! };
+//!
+//! note.dfu()
+//!     .get::<0>(&mut delay, 8192, Some(0))?
+//!     .wait_payload(&mut delay, &mut flash)?;
+//! ```
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use heapless::Vec;
+
+use crate::NoteError;
+
+/// Receives bytes as they are produced, e.g. a streamed response or the decoded payload.
+pub trait Sink {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError>;
+}
+
+impl<const N: usize> Sink for Vec<u8, N> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self.extend_from_slice(bytes)
+            .map_err(|_| NoteError::BufOverflow)
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), NoteError>> Sink for F {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self(bytes)
+    }
+}
+
+/// Decoded bytes are passed to the sink in batches of this size.
+const BATCH: usize = 48;
+
+/// Where the extractor is in the response.
+#[derive(Clone, Copy)]
+enum Scan {
+    /// Between strings.
+    Json,
+
+    /// In a string, which is a key of the top-level object if `key` is set.
+    String { key: bool, escape: bool },
+
+    /// In the string value of the field.
+    Value { escape: bool },
+}
+
+/// Extracts a base64 encoded string field of the top-level object of a JSON response while it is
+/// streamed, passing the decoded bytes to a sink. The rest of the response is passed on with the
+/// value of the field left empty, so that it can be deserialized when it is complete.
+pub struct Base64Field<'a, S: Sink> {
+    key: &'a [u8],
+    sink: &'a mut S,
+
+    scan: Scan,
+
+    /// Nesting depth of objects and arrays.
+    depth: usize,
+
+    /// A key of the top-level object is next.
+    expect_key: bool,
+
+    /// Bytes of the current key that match the key of the field, `None` if it does not match.
+    matched: Option<usize>,
+
+    /// The value of the field is next.
+    expect_value: bool,
+
+    /// Sextets of the current base64 quantum, and how many of them are padding.
+    bits: u32,
+    sextets: u8,
+    padding: u8,
+
+    /// Decoded bytes.
+    decoded: usize,
+}
+
+impl<'a, S: Sink> Base64Field<'a, S> {
+    /// Extract the field `key`, decoding it into `sink`.
+    pub fn new(key: &'a str, sink: &'a mut S) -> Self {
+        Base64Field {
+            key: key.as_bytes(),
+            sink,
+            scan: Scan::Json,
+            depth: 0,
+            expect_key: false,
+            matched: None,
+            expect_value: false,
+            bits: 0,
+            sextets: 0,
+            padding: 0,
+            decoded: 0,
+        }
+    }
+
+    /// Number of bytes decoded so far.
+    pub fn decoded(&self) -> usize {
+        self.decoded
+    }
+
+    /// Feed the next bytes of the response. The decoded field is written to the sink, and the rest
+    /// of the response to `rest`.
+    pub fn feed(&mut self, bytes: &[u8], rest: &mut impl Sink) -> Result<(), NoteError> {
+        let mut out: Vec<u8, BATCH> = Vec::new();
+
+        // Start of the bytes that are passed on to `rest`.
+        let mut start = match self.scan {
+            Scan::Value { .. } => None,
+            _ => Some(0),
+        };
+
+        for (i, &b) in bytes.iter().enumerate() {
+            match self.scan {
+                Scan::Json => match b {
+                    b'"' if self.depth == 1 && self.expect_value => {
+                        self.expect_value = false;
+                        self.scan = Scan::Value { escape: false };
+
+                        if let Some(s) = start.take() {
+                            rest.write(&bytes[s..=i])?;
+                        }
+                    }
+                    b'"' => {
+                        let key = self.depth == 1 && self.expect_key;
+                        self.expect_key = false;
+                        self.expect_value = false;
+                        self.matched = key.then_some(0);
+                        self.scan = Scan::String { key, escape: false };
+                    }
+                    b'{' | b'[' => {
+                        self.depth += 1;
+                        self.expect_key = b == b'{' && self.depth == 1;
+                        self.expect_value = false;
+                    }
+                    b'}' | b']' => {
+                        self.depth = self.depth.saturating_sub(1);
+                        self.expect_value = false;
+                    }
+                    b',' if self.depth == 1 => {
+                        self.expect_key = true;
+                        self.expect_value = false;
+                    }
+                    b':' | b' ' | b'\t' | b'\r' | b'\n' => {}
+                    _ => self.expect_value = false,
+                },
+
+                Scan::String { key, escape: true } => {
+                    self.matched = None;
+                    self.scan = Scan::String { key, escape: false };
+                }
+                Scan::String { key, escape: false } => match b {
+                    b'\\' => self.scan = Scan::String { key, escape: true },
+                    b'"' => {
+                        self.expect_value = key && self.matched == Some(self.key.len());
+                        self.scan = Scan::Json;
+                    }
+                    _ => {
+                        self.matched = self
+                            .matched
+                            .filter(|&m| self.key.get(m) == Some(&b))
+                            .map(|m| m + 1);
+                    }
+                },
+
+                Scan::Value { escape: true } => {
+                    // Only an escaped `/` may occur in base64.
+                    if b != b'/' {
+                        return Err(invalid());
+                    }
+                    self.scan = Scan::Value { escape: false };
+                    self.decode(b, &mut out)?;
+                }
+                Scan::Value { escape: false } => match b {
+                    b'\\' => self.scan = Scan::Value { escape: true },
+                    b'"' => {
+                        self.end(&mut out)?;
+                        self.flush(&mut out)?;
+                        self.scan = Scan::Json;
+                        start = Some(i);
+                    }
+                    _ => self.decode(b, &mut out)?,
+                },
+            }
+        }
+
+        self.flush(&mut out)?;
+
+        match start {
+            Some(s) => rest.write(&bytes[s..]),
+            None => Ok(()),
+        }
+    }
+
+    /// Decode a base64 character.
+    fn decode(&mut self, c: u8, out: &mut Vec<u8, BATCH>) -> Result<(), NoteError> {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' if self.sextets >= 2 => {
+                self.padding += 1;
+                0
+            }
+            _ => return Err(invalid()),
+        };
+
+        // Nothing but padding after padding.
+        if self.padding > 0 && c != b'=' {
+            return Err(invalid());
+        }
+
+        self.bits = self.bits << 6 | v as u32;
+        self.sextets += 1;
+
+        if self.sextets == 4 {
+            let n = 3 - self.padding as usize;
+            self.emit(&self.bits.to_be_bytes()[1..1 + n], out)?;
+
+            self.bits = 0;
+            self.sextets = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Decode the last quantum at the end of the value, which may be unpadded.
+    fn end(&mut self, out: &mut Vec<u8, BATCH>) -> Result<(), NoteError> {
+        let (bits, sextets) = (self.bits, self.sextets);
+
+        self.bits = 0;
+        self.sextets = 0;
+        self.padding = 0;
+
+        match sextets {
+            0 => Ok(()),
+            2 => self.emit(&[(bits >> 4) as u8], out),
+            3 => self.emit(&[(bits >> 10) as u8, (bits >> 2) as u8], out),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8], out: &mut Vec<u8, BATCH>) -> Result<(), NoteError> {
+        for &b in bytes {
+            if out.is_full() {
+                self.flush(out)?;
+            }
+            out.push(b).ok();
+        }
+
+        self.decoded += bytes.len();
+        Ok(())
+    }
+
+    fn flush(&mut self, out: &mut Vec<u8, BATCH>) -> Result<(), NoteError> {
+        if !out.is_empty() {
+            self.sink.write(out)?;
+            out.clear();
+        }
+        Ok(())
+    }
+}
+
+fn invalid() -> NoteError {
+    error!("stream: invalid base64 in response.");
+    NoteError::new_desererror(b"invalid base64")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::fixture::{notecard, recovers};
+    use crate::emulator::Emulator;
+    use crate::Notecard;
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use serde_json::{json, Value};
+
+    fn extract(
+        response: &[u8],
+        split: usize,
+    ) -> Result<(std::vec::Vec<u8>, Vec<u8, 256>), NoteError> {
+        let mut payload: Vec<u8, 1024> = Vec::new();
+        let mut rest: Vec<u8, 256> = Vec::new();
+
+        let mut field = Base64Field::new("payload", &mut payload);
+        for chunk in response.chunks(split) {
+            field.feed(chunk, &mut rest)?;
+        }
+        assert_eq!(field.decoded(), payload.len());
+
+        Ok((payload.to_vec(), rest))
+    }
+
+    #[test]
+    fn payload_in_chunks() {
+        for len in [0, 1, 2, 3, 4, 100, 500] {
+            let data: std::vec::Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let mut b64 = [0u8; 1024];
+            let sz = base64::encode_config_slice(&data, base64::STANDARD, &mut b64);
+            let response = format!(
+                r#"{{"note":"a","body":{{"payload":"x","n":[1,{{"payload":"y"}}]}},"payload":"{}","time":4}}"#,
+                core::str::from_utf8(&b64[..sz]).unwrap()
+            );
+
+            for split in [1, 2, 3, 5, 64, 1024] {
+                let (payload, rest) = extract(response.as_bytes(), split).unwrap();
+                assert_eq!(payload, data);
+                assert_eq!(
+                    &rest[..],
+                    br#"{"note":"a","body":{"payload":"x","n":[1,{"payload":"y"}]},"payload":"","time":4}"#
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn payload_escaped_and_unpadded() {
+        let (payload, rest) = extract(br#"{ "payload" : "AP\/+AA" }"#, 3).unwrap();
+        assert_eq!(payload, [0x00, 0xff, 0xfe, 0x00]);
+        assert_eq!(&rest[..], br#"{ "payload" : "" }"#);
+    }
+
+    #[test]
+    fn payload_not_a_string() {
+        let (payload, rest) = extract(br#"{"payload":null,"err":"x {io}"}"#, 4).unwrap();
+        assert!(payload.is_empty());
+        assert_eq!(&rest[..], br#"{"payload":null,"err":"x {io}"}"#);
+    }
+
+    #[test]
+    fn invalid_base64() {
+        for response in [
+            &br#"{"payload":"AA=A"}"#[..],
+            br#"{"payload":"A"}"#,
+            br#"{"payload":"AA\n"}"#,
+            br#"{"payload":"AA\"}"#,
+        ] {
+            assert!(matches!(
+                extract(response, 2),
+                Err(NoteError::DeserError(_))
+            ));
+        }
+    }
+
+    fn firmware() -> (std::vec::Vec<u8>, String) {
+        let data: std::vec::Vec<u8> = (0..3000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut b64 = [0u8; 4096];
+        let sz = base64::encode_config_slice(&data, base64::STANDARD, &mut b64);
+        (data, String::from_utf8(b64[..sz].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn stream_payload() {
+        let emu = Emulator::new();
+        let (data, b64) = firmware();
+        emu.on("dfu.get", move |_| json!({ "payload": b64 }));
+
+        let mut note: Notecard<_, 256> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay::new();
+
+        let mut firmware: heapless::Vec<u8, 4096> = heapless::Vec::new();
+        let r = note
+            .dfu()
+            .get::<0>(&mut delay, data.len(), Some(0))
+            .unwrap()
+            .wait_payload(&mut delay, &mut firmware)
+            .unwrap();
+        assert_eq!(r.payload, "");
+        assert_eq!(&firmware[..], &data[..]);
+        assert_eq!(emu.pending(), 0);
+
+        // Errors are deserialized from the rest of the response.
+        let r = note
+            .note()
+            .get::<()>(&mut delay, "missing.db", "1", false, false)
+            .unwrap()
+            .wait_payload(&mut delay, &mut firmware);
+        assert!(matches!(r, Err(NoteError::NotecardErr(_))));
+        recovers(&mut note, &emu);
+    }
+
+    #[test]
+    fn stream_response() {
+        let emu = Emulator::new();
+        let (_, b64) = firmware();
+
+        let mut note: Notecard<_, 256> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        note.note()
+            .update(&mut delay, "fw.db", "1", None::<()>, Some(&b64), false)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        let mut response = std::vec::Vec::new();
+        let mut chunks = 0;
+        note.note()
+            .get::<()>(&mut delay, "fw.db", "1", false, false)
+            .unwrap()
+            .wait_stream(&mut delay, &mut |bytes: &[u8]| {
+                response.extend_from_slice(bytes);
+                chunks += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert!(chunks > 1);
+
+        let response: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["payload"], b64.as_str());
+
+        let r = note
+            .note()
+            .get::<()>(&mut delay, "missing.db", "1", false, false)
+            .unwrap()
+            .wait_stream(&mut delay, &mut heapless::Vec::<u8, 256>::new());
+        assert!(matches!(r, Err(NoteError::NotecardErr(_))));
+        recovers(&mut note, &emu);
+    }
+}
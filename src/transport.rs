@@ -0,0 +1,46 @@
+//! Transports for talking to the Notecard.
+//!
+//! The Notecard can be connected over I2C, using the
+//! [serial-over-I2C protocol](https://dev.blues.io/notecard/notecard-guides/serial-over-i2c-protocol/),
+//! or over a UART where requests and responses are separated by newlines. With the `std` feature
+//! the newline separated protocol can also be used over any `std::io` stream, see [`Io`]. The [`Notecard`] driver
+//! does the request chunking, polling and buffering, while the [`Transport`] moves the bytes.
+//!
+//! [`Notecard`]: crate::Notecard
+
+use super::NoteError;
+
+pub(crate) mod i2c;
+mod serial;
+
+#[cfg(feature = "std")]
+mod io;
+
+pub use i2c::I2c;
+pub use serial::Serial;
+
+#[cfg(feature = "std")]
+pub use io::Io;
+
+/// A bus the Notecard is connected to.
+pub trait Transport {
+    /// The `embedded-hal` version of the bus, [`Eh0`](crate::Eh0) or [`Eh1`](crate::Eh1). The
+    /// driver takes a [`Delay`](crate::Delay) of the same version.
+    type Hal;
+
+    /// Check if the Notecard is connected and responding.
+    fn ping(&mut self) -> bool;
+
+    /// Query the Notecard for the number of bytes that are ready to be read. The transport may
+    /// return an estimate, but a non-zero value means that a response is on its way.
+    fn data_query(&mut self) -> Result<usize, NoteError>;
+
+    /// Read the next part of a response into the start of `buf`, `avail` is the number of bytes
+    /// last reported as available. Returns the number of bytes read and the number of bytes still
+    /// remaining, which is `0` once the full response has been read. The rest of `buf` may be used
+    /// while reading, the I2C transport reads at most `buf.len() - 2` bytes to fit the header.
+    fn read(&mut self, avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError>;
+
+    /// Write a chunk of a request.
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError>;
+}
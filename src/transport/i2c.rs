@@ -0,0 +1,190 @@
+//! Serial-over-I2C: <https://dev.blues.io/notecard/notecard-guides/serial-over-i2c-protocol/>
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+use heapless::Vec;
+
+use super::Transport;
+use crate::error::{ErrorKind, Unclassified};
+use crate::{Eh0, NoteError};
+
+/// The Notecard connected over I2C. The errors of the I2C device are classified by `K`, see
+/// [`ErrorKind`].
+pub struct I2c<IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, K = Unclassified> {
+    i2c: IOM,
+    addr: u8,
+    _kind: PhantomData<K>,
+}
+
+impl<IOM: Write<SevenBitAddress> + Read<SevenBitAddress>> I2c<IOM> {
+    pub fn new(i2c: IOM, addr: u8) -> I2c<IOM> {
+        I2c {
+            i2c,
+            addr,
+            _kind: PhantomData,
+        }
+    }
+}
+
+impl<IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, K> I2c<IOM, K> {
+    /// Classify the errors of the I2C device with `K2`.
+    pub fn with_error_kind<K2>(self) -> I2c<IOM, K2> {
+        I2c {
+            i2c: self.i2c,
+            addr: self.addr,
+            _kind: PhantomData,
+        }
+    }
+
+    /// Free the I2C device.
+    pub fn free(self) -> IOM {
+        self.i2c
+    }
+
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+}
+
+impl<IOM, K> I2cBus for I2c<IOM, K>
+where
+    IOM: Write<SevenBitAddress> + Read<SevenBitAddress>,
+    K: ErrorKind<<IOM as Write>::Error> + ErrorKind<<IOM as Read>::Error>,
+{
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self.i2c
+            .write(self.addr, bytes)
+            .map_err(|e| NoteError::I2cWriteError(K::kind(&e)))
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), NoteError> {
+        self.i2c
+            .read(self.addr, buf)
+            .map_err(|e| NoteError::I2cReadError(K::kind(&e)))
+    }
+}
+
+impl<IOM, K> Transport for I2c<IOM, K>
+where
+    IOM: Write<SevenBitAddress> + Read<SevenBitAddress>,
+    K: ErrorKind<<IOM as Write>::Error> + ErrorKind<<IOM as Read>::Error>,
+{
+    type Hal = Eh0;
+
+    fn ping(&mut self) -> bool {
+        block_on(I2cBus::write(self, &[])).is_ok()
+    }
+
+    fn data_query(&mut self) -> Result<usize, NoteError> {
+        block_on(data_query(self))
+    }
+
+    fn read(&mut self, avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError> {
+        block_on(read(self, avail, buf))
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        block_on(write(self, chunk))
+    }
+}
+
+/// Plain I2C transfers to the Notecard address. The serial-over-I2C protocol on top is shared
+/// between the `embedded-hal` versions and the async driver. The transfers of blocking buses
+/// complete when they are first polled, and are run with [`block_on`].
+pub(crate) trait I2cBus {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError>;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), NoteError>;
+}
+
+/// Run a transfer of a blocking bus to completion.
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = pin!(f);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(r) = f.as_mut().poll(&mut cx) {
+            return r;
+        }
+    }
+}
+
+pub(crate) async fn data_query(bus: &mut impl I2cBus) -> Result<usize, NoteError> {
+    // Ask for reading, but with zero bytes allocated.
+    bus.write(&[0, 0]).await?;
+
+    let mut buf = [0u8; 2];
+
+    // Read available bytes to read
+    bus.read(&mut buf).await?;
+
+    let available = buf[0] as usize;
+    let sent = buf[1] as usize;
+
+    trace!("avail = {}, sent = {}", available, sent);
+
+    if sent > 0 {
+        error!(
+            "data query: bytes sent when querying available bytes: {}",
+            sent
+        );
+        Err(NoteError::RemainingData)
+    } else {
+        Ok(available)
+    }
+}
+
+pub(crate) async fn read(
+    bus: &mut impl I2cBus,
+    avail: usize,
+    buf: &mut [u8],
+) -> Result<(usize, usize), NoteError> {
+    // Notecard header (2 bytes) + chunk to read, the chunk is moved into place after reading.
+    let sz = avail.min(buf.len().saturating_sub(2)).min(u8::MAX as usize);
+    let bytes = buf.get_mut(..sz + 2).ok_or(NoteError::BufOverflow)?;
+
+    debug!("asking to read: {} of available {} bytes", sz, avail);
+
+    // Ask for reading `sz` bytes
+    bus.write(&[0, sz as u8]).await?;
+
+    // Read bytes
+    bus.read(bytes).await?;
+
+    let available = bytes[0] as usize;
+    let sent = bytes[1] as usize;
+
+    // The Notecard may send fewer bytes than asked for.
+    let sent = sent.min(sz);
+    bytes.copy_within(2..2 + sent, 0);
+
+    trace!("read:  {}", unsafe {
+        core::str::from_utf8_unchecked(&bytes[..sent])
+    });
+
+    trace!("avail = {}, sent = {}", available, sent);
+
+    Ok((sent, available))
+}
+
+pub(crate) async fn write(bus: &mut impl I2cBus, chunk: &[u8]) -> Result<(), NoteError> {
+    // Chunk length is limited to one byte, and the Notecard firmware does not accept more than
+    // 127 bytes at a time.
+    let mut buf = Vec::<u8, 128>::new();
+    buf.push(chunk.len() as u8)
+        .map_err(|_| NoteError::BufOverflow)?;
+    buf.extend_from_slice(chunk)
+        .map_err(|_| NoteError::BufOverflow)?;
+
+    trace!("note: sending chunk: {} => {}", &buf, unsafe {
+        core::str::from_utf8_unchecked(&buf)
+    });
+
+    bus.write(&buf).await
+}
@@ -0,0 +1,228 @@
+//! Host transport over `std::io`: requests and responses are JSON objects terminated by a newline,
+//! like over [`Serial`](super::Serial).
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+
+use super::Transport;
+use crate::error::BusError;
+use crate::{Eh0, NoteError};
+
+/// The Notecard connected through any `std::io::Read + std::io::Write`, e.g. a USB CDC device
+/// file (`/dev/ttyACM0`), a pty or a TCP socket.
+///
+/// The stream should be non-blocking or have a read timeout, a blocking read will not return
+/// before the Notecard sends something. Errors of the stream are classified by their
+/// `std::io::ErrorKind`, a stream that ends or breaks is [`BusError::Disconnected`] and not
+/// retried. Note that the driver logs through `defmt`, so the host binary must still provide a
+/// `defmt` global logger, see the README.
+pub struct Io<S: io::Read + io::Write> {
+    stream: S,
+
+    /// Bytes that have been read from the stream, but not yet consumed.
+    pending: VecDeque<u8>,
+}
+
+impl<S: io::Read + io::Write> Io<S> {
+    pub fn new(stream: S) -> Io<S> {
+        Io {
+            stream,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Free the stream.
+    pub fn free(self) -> S {
+        self.stream
+    }
+
+    /// Read whatever is waiting on the stream into `pending`. Returns the number of bytes read.
+    fn fill(&mut self) -> Result<usize, NoteError> {
+        let mut buf = [0u8; 256];
+
+        match self.stream.read(&mut buf) {
+            Ok(0) => {
+                error!("io: end of stream.");
+                Err(NoteError::SerialReadError(BusError::Disconnected))
+            }
+            Ok(n) => {
+                self.pending.extend(&buf[..n]);
+                Ok(n)
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(NoteError::SerialReadError(e.kind().into())),
+        }
+    }
+}
+
+impl<S: io::Read + io::Write> Transport for Io<S> {
+    type Hal = Eh0;
+
+    /// There is no way to tell whether the Notecard is listening without making a request. This
+    /// always returns `true`.
+    fn ping(&mut self) -> bool {
+        true
+    }
+
+    fn data_query(&mut self) -> Result<usize, NoteError> {
+        if self.pending.is_empty() {
+            self.fill()?;
+        }
+
+        Ok(self.pending.len())
+    }
+
+    /// Read until the response is terminated by a newline. Returns `1` remaining byte until the
+    /// newline has been read.
+    fn read(&mut self, _avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError> {
+        let mut n = 0;
+
+        while n < buf.len() {
+            let b = match self.pending.pop_front() {
+                Some(b) => b,
+                None if self.fill()? > 0 => continue,
+                None => break,
+            };
+
+            buf[n] = b;
+            n += 1;
+
+            if b == b'\n' {
+                return Ok((n, 0));
+            }
+        }
+
+        Ok((n, 1))
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        self.stream
+            .write_all(chunk)
+            .and_then(|_| self.stream.flush())
+            .map_err(|e| NoteError::SerialWriteError(e.kind().into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Notecard, NotecardConfig, StdDelay};
+
+    /// Stream answering every request with a canned response.
+    struct Stream {
+        written: Vec<u8>,
+        response: io::Cursor<Vec<u8>>,
+    }
+
+    impl io::Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.written.ends_with(b"\n") {
+                match self.response.read(buf)? {
+                    0 => Err(ErrorKind::WouldBlock.into()),
+                    n => Ok(n),
+                }
+            } else {
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+
+    impl io::Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn request_response() {
+        let stream = Stream {
+            written: Vec::new(),
+            response: io::Cursor::new(b"{\"status\":\"{normal}\",\"storage\":8}\r\n".to_vec()),
+        };
+
+        let mut c: Notecard<Io<Stream>> =
+            Notecard::new_with_transport(Io::new(stream), NotecardConfig::default());
+        let mut delay = StdDelay;
+
+        c.initialize(&mut delay).unwrap();
+        let status = c
+            .card()
+            .status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(status.status, "{normal}");
+        assert_eq!(status.storage, 8);
+
+        let (iom, _) = c.suspend();
+        assert_eq!(iom.free().written, b"{\"req\":\"card.status\"}\n");
+    }
+
+    /// Stream failing every read and write with an error of `kind`.
+    struct Failing(ErrorKind);
+
+    impl io::Read for Failing {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+    }
+
+    impl io::Write for Failing {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut io = Io::new(Failing(ErrorKind::BrokenPipe));
+        let e = io.write(b"{}\n").unwrap_err();
+        assert!(matches!(
+            e,
+            NoteError::SerialWriteError(BusError::Disconnected)
+        ));
+        assert!(!e.is_transient());
+        let e = io.data_query().unwrap_err();
+        assert!(matches!(
+            e,
+            NoteError::SerialReadError(BusError::Disconnected)
+        ));
+
+        let mut io = Io::new(Failing(ErrorKind::TimedOut));
+        let e = io.write(b"{}\n").unwrap_err();
+        assert!(matches!(e, NoteError::SerialWriteError(BusError::TimedOut)));
+        assert!(e.is_transient());
+        assert_eq!(io.data_query().unwrap(), 0);
+
+        let mut io = Io::new(Failing(ErrorKind::InvalidData));
+        let e = io.data_query().unwrap_err();
+        assert!(matches!(e, NoteError::SerialReadError(BusError::Other)));
+        assert!(e.is_transient());
+
+        let mut io = Io::new(io::Cursor::new(Vec::new()));
+        let e = io.data_query().unwrap_err();
+        assert!(matches!(
+            e,
+            NoteError::SerialReadError(BusError::Disconnected)
+        ));
+    }
+}
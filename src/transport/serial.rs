@@ -0,0 +1,128 @@
+//! Serial (UART): requests and responses are JSON objects terminated by a newline.
+
+use core::marker::PhantomData;
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use embedded_hal::serial;
+
+use super::Transport;
+use crate::error::{ErrorKind, Unclassified};
+use crate::{Eh0, NoteError};
+
+/// The Notecard connected over a UART.
+///
+/// The Notecard does not wait for the host to read, so the UART should be buffered (e.g. interrupt
+/// or DMA driven) to not lose bytes while the driver sleeps between polls. The errors of the UART
+/// are classified by `K`, see [`ErrorKind`].
+pub struct Serial<U: serial::Read<u8> + serial::Write<u8>, K = Unclassified> {
+    uart: U,
+
+    /// Byte read while querying for available data.
+    peek: Option<u8>,
+
+    _kind: PhantomData<K>,
+}
+
+impl<U: serial::Read<u8> + serial::Write<u8>> Serial<U> {
+    pub fn new(uart: U) -> Serial<U> {
+        Serial {
+            uart,
+            peek: None,
+            _kind: PhantomData,
+        }
+    }
+}
+
+impl<U, K> Serial<U, K>
+where
+    U: serial::Read<u8> + serial::Write<u8>,
+    K: ErrorKind<<U as serial::Read<u8>>::Error> + ErrorKind<<U as serial::Write<u8>>::Error>,
+{
+    /// Classify the errors of the UART with `K2`.
+    pub fn with_error_kind<K2>(self) -> Serial<U, K2> {
+        Serial {
+            uart: self.uart,
+            peek: self.peek,
+            _kind: PhantomData,
+        }
+    }
+
+    /// Free the UART device.
+    pub fn free(self) -> U {
+        self.uart
+    }
+
+    /// Read a byte if one is waiting.
+    fn read_byte(&mut self) -> Result<Option<u8>, NoteError> {
+        if let Some(b) = self.peek.take() {
+            return Ok(Some(b));
+        }
+
+        match self.uart.read() {
+            Ok(b) => Ok(Some(b)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(e)) => Err(NoteError::SerialReadError(K::kind(&e))),
+        }
+    }
+}
+
+impl<U, K> Transport for Serial<U, K>
+where
+    U: serial::Read<u8> + serial::Write<u8>,
+    K: ErrorKind<<U as serial::Read<u8>>::Error> + ErrorKind<<U as serial::Write<u8>>::Error>,
+{
+    type Hal = Eh0;
+
+    /// A UART has no acknowledgement, so there is no way to tell whether the Notecard is listening
+    /// without making a request. This always returns `true`.
+    fn ping(&mut self) -> bool {
+        true
+    }
+
+    /// The number of bytes waiting is not known over a UART, this returns `1` if any byte has
+    /// arrived.
+    fn data_query(&mut self) -> Result<usize, NoteError> {
+        if self.peek.is_none() {
+            self.peek = self.read_byte()?;
+        }
+
+        Ok(self.peek.is_some() as usize)
+    }
+
+    /// Read until the response is terminated by a newline. Returns `1` remaining byte until the
+    /// newline has been read.
+    fn read(&mut self, _avail: usize, buf: &mut [u8]) -> Result<(usize, usize), NoteError> {
+        let mut n = 0;
+
+        while n < buf.len() {
+            match self.read_byte()? {
+                Some(b) => {
+                    buf[n] = b;
+                    n += 1;
+
+                    if b == b'\n' {
+                        trace!("read: {} bytes, newline received.", n);
+                        return Ok((n, 0));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        trace!("read: {} bytes, waiting for more.", n);
+        Ok((n, 1))
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        trace!("note: sending chunk: {}", unsafe {
+            core::str::from_utf8_unchecked(chunk)
+        });
+
+        for b in chunk {
+            nb::block!(self.uart.write(*b))
+                .map_err(|e| NoteError::SerialWriteError(K::kind(&e)))?;
+        }
+
+        nb::block!(self.uart.flush()).map_err(|e| NoteError::SerialWriteError(K::kind(&e)))
+    }
+}
@@ -2,25 +2,39 @@
 
 #[allow(unused_imports)]
 use defmt::{debug, error, info, trace, warn};
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
 use serde::{Deserialize, Serialize};
 
-use super::{str_string, FutureResponse, NoteError, Notecard};
+use super::{str_string, Attn, Clock, Delay, FutureResponse, NoteError, Notecard, NotecardRequest};
+use crate::transport::Transport;
 
-pub struct Web<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> {
-    note: &'a mut Notecard<IOM, BS>,
+/// Time the Notecard waits for a web request when `seconds` is not given (s).
+const DEFAULT_SECONDS: u16 = 90;
+
+/// Time added to the `seconds` of a web request when waiting for the response (ms).
+const TIMEOUT_MARGIN: u32 = 5000;
+
+/// Timeout while waiting for the response to a web request waiting for `seconds` (ms).
+fn timeout(seconds: Option<u16>) -> u32 {
+    let seconds = seconds.unwrap_or(DEFAULT_SECONDS) as u32;
+    seconds * 1000 + TIMEOUT_MARGIN
+}
+
+pub struct Web<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> {
+    note: &'a mut Notecard<IOM, BS, C, A>,
 }
 
-impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> Web<'a, IOM, BS> {
-    pub fn from(note: &mut Notecard<IOM, BS>) -> Web<'_, IOM, BS> {
+impl<'a, IOM: Transport, const BS: usize, C: Clock, A: Attn> Web<'a, IOM, BS, C, A> {
+    pub fn from(note: &mut Notecard<IOM, BS, C, A>) -> Web<'_, IOM, BS, C, A> {
         Web { note }
     }
 
     /// Performs a simple HTTP or HTTPS POST request against an external endpoint, and returns the response to the Notecard.
+    ///
+    /// The response is waited for until the Notecard gives up on the request after `seconds`
+    /// (90 s by default), unless the `response_timeout` of the driver is longer.
     pub fn post<T: Serialize + Default>(
         self,
-        delay: &mut impl DelayMs<u16>,
+        delay: &mut impl Delay<IOM::Hal>,
         route: &str,
         name: Option<&str>,
         body: Option<T>,
@@ -30,11 +44,10 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> W
         max: Option<u16>,
         verify: Option<bool>,
         nasync: Option<bool>,
-    ) -> Result<FutureResponse<'a, res::Post, IOM, BS>, NoteError> {
+    ) -> Result<FutureResponse<'a, res::Post, IOM, BS, C, A>, NoteError> {
         self.note.request(
             delay,
             req::Post::<T> {
-                req: "web.post",
                 route: heapless::String::try_from(route).map_err(NoteError::string_err)?,
                 name: str_string(name)?,
                 body,
@@ -46,17 +59,16 @@ impl<'a, IOM: Write<SevenBitAddress> + Read<SevenBitAddress>, const BS: usize> W
                 nasync,
             },
         )?;
+
         Ok(FutureResponse::from(self.note))
     }
 }
 
-mod req {
+pub mod req {
     use super::*;
 
     #[derive(Deserialize, Serialize, Debug, defmt::Format, Default)]
     pub struct Post<'a, T: Serialize + Default> {
-        pub req: &'static str,
-
         pub route: heapless::String<256>,
 
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -83,6 +95,15 @@ mod req {
         #[serde(rename = "async", skip_serializing_if = "Option::is_none")]
         pub nasync: Option<bool>,
     }
+
+    impl<T: Serialize + Default> NotecardRequest for Post<'_, T> {
+        const NAME: &'static str = "web.post";
+        type Response = res::Post;
+
+        fn timeout(&self) -> Option<u32> {
+            Some(timeout(self.seconds))
+        }
+    }
 }
 
 pub mod res {
@@ -98,3 +119,41 @@ pub mod res {
         // length: Option<u32>,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::emulator::fixture::{notecard, recovers};
+    use crate::emulator::{Emulator, Fault};
+    use crate::Notecard;
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use serde_json::Value;
+
+    #[test]
+    fn post_timeout() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |c| c.response_timeout = 100);
+        let mut delay = NoopDelay::new();
+
+        // Waits for as long as the Notecard waits for the web request.
+        emu.respond("web.post", r#"{"result":200}"#);
+        emu.inject(Fault::Slow(100));
+        note.web()
+            .post::<Value>(
+                &mut delay,
+                "route",
+                None,
+                None,
+                None,
+                None,
+                Some(5),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        recovers(&mut note, &emu);
+    }
+}
@@ -0,0 +1,399 @@
+//! Writing requests to the Notecard in chunks and segments while they are serialized, so that
+//! only a single chunk is buffered. The async driver can not write to the bus while a request is
+//! serialized, it serializes the request again for every segment and buffers only that, see
+//! [`Part`].
+
+#[allow(unused_imports)]
+use defmt::{debug, error, info, trace, warn};
+use heapless::Vec;
+
+use crate::crc::{self, Crc32};
+use crate::protocol::Pacing;
+use crate::stream::Sink;
+use crate::transport::Transport;
+#[cfg(feature = "async")]
+use crate::SEGMENT_LENGTH_MAX;
+use crate::{Delay, NoteError, CHUNK_LENGTH_MAX};
+
+/// Writes chunks to the Notecard, spaced by [`Pacing`].
+struct Chunks<'a, IOM: Transport, D: Delay<IOM::Hal>> {
+    iom: &'a mut IOM,
+    delay: &'a mut D,
+    pacing: Pacing,
+}
+
+impl<IOM: Transport, D: Delay<IOM::Hal>> Chunks<'_, IOM, D> {
+    fn send(&mut self, chunk: &[u8]) -> Result<(), NoteError> {
+        self.iom.write(chunk)?;
+        self.delay.delay_ms(self.pacing.chunk(chunk.len()));
+
+        Ok(())
+    }
+}
+
+/// Splits a request into chunks of `chunk_length` bytes. The segment length must be a whole
+/// number of chunks, see [`NotecardConfig::lengths`](crate::NotecardConfig).
+pub(crate) struct Writer<'a, IOM: Transport, D: Delay<IOM::Hal>> {
+    chunks: Chunks<'a, IOM, D>,
+    chunk: Vec<u8, CHUNK_LENGTH_MAX>,
+    chunk_length: usize,
+}
+
+impl<'a, IOM: Transport, D: Delay<IOM::Hal>> Writer<'a, IOM, D> {
+    pub(crate) fn new(
+        iom: &'a mut IOM,
+        delay: &'a mut D,
+        chunk_length: usize,
+        pacing: Pacing,
+    ) -> Self {
+        Writer {
+            chunks: Chunks { iom, delay, pacing },
+            chunk: Vec::new(),
+            chunk_length,
+        }
+    }
+
+    /// Write the last chunk, and wait for the last segment.
+    pub(crate) fn finish(mut self) -> Result<(), NoteError> {
+        if !self.chunk.is_empty() {
+            self.chunks.send(&self.chunk)?;
+        }
+
+        self.chunks.delay.delay_ms(self.chunks.pacing.finish());
+
+        Ok(())
+    }
+}
+
+impl<IOM: Transport, D: Delay<IOM::Hal>> Sink for Writer<'_, IOM, D> {
+    fn write(&mut self, mut bytes: &[u8]) -> Result<(), NoteError> {
+        while !bytes.is_empty() {
+            // Whole chunks are written without copying them.
+            if self.chunk.is_empty() && bytes.len() >= self.chunk_length {
+                let (chunk, rest) = bytes.split_at(self.chunk_length);
+                self.chunks.send(chunk)?;
+                bytes = rest;
+                continue;
+            }
+
+            let n = (self.chunk_length - self.chunk.len()).min(bytes.len());
+            let (part, rest) = bytes.split_at(n);
+            self.chunk
+                .extend_from_slice(part)
+                .map_err(|_| NoteError::BufOverflow)?;
+            bytes = rest;
+
+            if self.chunk.len() == self.chunk_length {
+                self.chunks.send(&self.chunk)?;
+                self.chunk.clear();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The start of a serialized request, and of a command.
+const REQ: &[u8; 7] = br#"{"req":"#;
+const CMD: &[u8; 7] = br#"{"cmd":"#;
+
+/// Writes a serialized request to `W`, optionally turned into a command by replacing its `req`
+/// key with `cmd`, with the sequence number and CRC added (see [`crc`]) and terminated by a newline.
+pub(crate) struct RequestWriter<W: Sink> {
+    writer: W,
+
+    command: bool,
+
+    /// Sequence number, and CRC of the request so far.
+    crc: Option<(u16, Crc32)>,
+
+    /// The last byte is held back until it is known whether it ends the request, and the CRC
+    /// field should be added before it.
+    last: Option<u8>,
+
+    /// Length of the serialized request.
+    len: usize,
+}
+
+impl<W: Sink> RequestWriter<W> {
+    /// Write a request to `writer`, with the sequence number `seq` if CRCs are used.
+    pub(crate) fn new(writer: W, command: bool, seq: Option<u16>) -> Self {
+        RequestWriter {
+            writer,
+            command,
+            crc: seq.map(|seq| (seq, Crc32::new())),
+            last: None,
+            len: 0,
+        }
+    }
+
+    /// Write bytes as they go on the bus.
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        self.writer.write(bytes)
+    }
+
+    /// Add bytes of the serialized request.
+    fn push(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        let Some((&end, rest)) = bytes.split_last() else {
+            return Ok(());
+        };
+
+        self.len += bytes.len();
+
+        match &mut self.crc {
+            None => self.emit(bytes),
+            Some((_, crc)) => {
+                crc.update(bytes);
+
+                if let Some(last) = self.last.replace(end) {
+                    self.emit(&[last])?;
+                }
+                self.emit(rest)
+            }
+        }
+    }
+
+    /// Add the CRC field and newline. The writer is finished with [`into_inner`](Self::into_inner).
+    pub(crate) fn finish(&mut self) -> Result<(), NoteError> {
+        if self.command && self.len < REQ.len() {
+            return Err(NoteError::InvalidRequest);
+        }
+
+        match (self.crc, self.last.take()) {
+            (Some((seq, crc)), Some(b'}')) => {
+                if self.len > 2 {
+                    self.emit(b",")?;
+                }
+                self.emit(&crc::field(seq, crc.finish()))?;
+                self.emit(b"}")?;
+            }
+            (_, Some(last)) => self.emit(&[last])?,
+            _ => {}
+        }
+
+        self.emit(b"\n")
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Sink> Sink for RequestWriter<W> {
+    fn write(&mut self, mut bytes: &[u8]) -> Result<(), NoteError> {
+        while self.command && self.len < REQ.len() {
+            let Some((&b, rest)) = bytes.split_first() else {
+                return Ok(());
+            };
+
+            if b != REQ[self.len] {
+                return Err(NoteError::InvalidRequest);
+            }
+
+            self.push(&CMD[self.len..=self.len])?;
+            bytes = rest;
+        }
+
+        self.push(bytes)
+    }
+}
+
+/// Collects the part of a request from `skip` bytes on, at most `len` bytes. Serializing the
+/// request into the part is stopped with [`NoteError::BufOverflow`] once the part is full, and
+/// [`more`](Self::more) is set.
+#[cfg(feature = "async")]
+pub(crate) struct Part {
+    skip: usize,
+    len: usize,
+
+    pub(crate) bytes: Vec<u8, SEGMENT_LENGTH_MAX>,
+
+    /// The request continues after the part.
+    pub(crate) more: bool,
+}
+
+#[cfg(feature = "async")]
+impl Part {
+    pub(crate) fn new(skip: usize, len: usize) -> Part {
+        Part {
+            skip,
+            len: len.min(SEGMENT_LENGTH_MAX),
+            bytes: Vec::new(),
+            more: false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Sink for Part {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), NoteError> {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        let bytes = &bytes[skipped..];
+
+        let n = (self.len - self.bytes.len()).min(bytes.len());
+        self.bytes
+            .extend_from_slice(&bytes[..n])
+            .map_err(|_| NoteError::BufOverflow)?;
+
+        if n < bytes.len() {
+            self.more = true;
+            return Err(NoteError::BufOverflow);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::fixture::{notecard, recovers};
+    use crate::emulator::Emulator;
+    use crate::{NoteState, Notecard};
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use serde_json::json;
+
+    /// Write `request` through a [`RequestWriter`] into a buffer.
+    fn write<const N: usize>(
+        request: &[u8],
+        command: bool,
+        seq: Option<u16>,
+    ) -> Result<Vec<u8, N>, NoteError> {
+        let mut writer = RequestWriter::new(Vec::new(), command, seq);
+        writer.write(request)?;
+        writer.finish()?;
+        Ok(writer.into_inner())
+    }
+
+    #[test]
+    fn crc_field() {
+        let buf = write::<128>(br#"{"req":"card.status"}"#, false, Some(0x1a)).unwrap();
+
+        let crc = crc::crc32(&[br#"{"req":"card.status"}"#]);
+        let mut expected = std::vec::Vec::from(&br#"{"req":"card.status","#[..]);
+        expected.extend_from_slice(&crc::field(0x1a, crc));
+        expected.extend_from_slice(b"}\n");
+        assert_eq!(&buf[..], &expected[..]);
+
+        assert_eq!(crc::parse(&buf), Some((0x1a, crc, crc)));
+
+        let buf = write::<128>(br#"{"req":"card.status"}"#, true, None).unwrap();
+        assert_eq!(&buf[..], b"{\"cmd\":\"card.status\"}\n");
+    }
+
+    #[test]
+    fn crc_field_in_empty_object() {
+        let buf = write::<128>(b"{}", false, Some(1)).unwrap();
+        assert!(buf.starts_with(br#"{"crc":"0001:"#));
+        crc::check(&buf, 1).unwrap();
+    }
+
+    #[test]
+    fn crc_field_overflow() {
+        let r = write::<32>(br#"{"req":"card.status"}"#, false, Some(1));
+        assert!(matches!(r, Err(NoteError::BufOverflow)));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn parts() {
+        let request = br#"{"req":"note.add","body":{"temp":1}}"#;
+        let whole = write::<128>(request, false, Some(7)).unwrap();
+
+        let mut joined = std::vec::Vec::new();
+        loop {
+            let mut writer = RequestWriter::new(Part::new(joined.len(), 10), false, Some(7));
+            let r = writer.write(request).and_then(|_| writer.finish());
+            let part = writer.into_inner();
+
+            assert_eq!(r.is_err(), part.more);
+            assert!(part.bytes.len() <= 10);
+            joined.extend_from_slice(&part.bytes);
+
+            if !part.more {
+                break;
+            }
+        }
+
+        assert_eq!(&joined[..], &whole[..]);
+    }
+
+    #[test]
+    fn long_request_and_response() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        let status = "x".repeat(1000);
+        emu.respond("hub.sync.status", &json!({ "status": status }).to_string());
+
+        let text = "y".repeat(500);
+        note.hub()
+            .log(&mut delay, &text, false, false)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(emu.requests()[0]["text"], text.as_str());
+
+        let r = note
+            .hub()
+            .sync_status(&mut delay)
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+        assert_eq!(r.status.unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn request_larger_than_buffer() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_, 128> = notecard(&emu, |c| c.crc = true);
+        let mut delay = NoopDelay::new();
+
+        let payload = "QUJD".repeat(256);
+        // The emulator answers with an error if the CRC does not match.
+        note.note()
+            .add(
+                &mut delay,
+                Some("big.qo"),
+                None,
+                None::<()>,
+                Some(&payload),
+                false,
+            )
+            .unwrap()
+            .wait(&mut delay)
+            .unwrap();
+
+        let requests = emu.requests();
+        assert_eq!(requests.last().unwrap()["payload"], payload.as_str());
+    }
+
+    #[test]
+    fn commands() {
+        let emu = Emulator::new();
+        let mut note: Notecard<_> = notecard(&emu, |_| {});
+        let mut delay = NoopDelay::new();
+
+        note.hub().sync_cmd(&mut delay, true, None, None).unwrap();
+        assert!(matches!(note.proto.state, NoteState::Request));
+        assert_eq!(emu.requests()[0]["cmd"], "hub.sync");
+        assert_eq!(emu.pending(), 0);
+
+        note.note()
+            .add_cmd(
+                &mut delay,
+                Some("data.qo"),
+                Some("t"),
+                Some(json!({ "temp": 1 })),
+                None,
+                false,
+            )
+            .unwrap();
+        assert!(matches!(note.proto.state, NoteState::Request));
+        assert_eq!(emu.notes("data.qo")["t"]["body"]["temp"], 1);
+
+        recovers(&mut note, &emu);
+    }
+}